@@ -0,0 +1,300 @@
+use crate::parser::{Org, Scheduling, Section};
+use crate::{Interval, TimeUnit, Timestamp};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Which days an agenda covers and which headings it shows.
+#[derive(Clone, Debug)]
+pub struct AgendaQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Overdue items and upcoming deadlines are listed on this day, like org-agenda does.
+    pub today: NaiveDate,
+    /// Lead time for deadlines without a warning cookie.
+    pub deadline_warning_days: u32,
+    /// Headings must carry all of these tags, inherited ones included.
+    pub tags: Vec<String>,
+    /// Headings must have one of these TODO keywords.
+    pub todo: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AgendaKind {
+    Deadline,
+    Scheduled,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AgendaItem {
+    pub kind: AgendaKind,
+    pub title: String,
+    pub todo: Option<String>,
+    pub done: bool,
+    pub priority: Option<char>,
+    pub tags: Vec<String>,
+    pub file: Option<String>,
+    pub line: usize,
+    pub id: Option<String>,
+    /// Date of the occurrence.
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+    pub end_time: Option<NaiveTime>,
+    /// Days from the agenda day to `date`; negative when overdue, positive for
+    /// deadlines shown ahead of time.
+    pub days: i64,
+    pub timestamp: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AgendaDay {
+    pub date: NaiveDate,
+    pub items: Vec<AgendaItem>,
+}
+
+/// Scheduled items and deadlines of `orgs` grouped by day, every day of the range included.
+pub fn agenda<'a>(orgs: impl IntoIterator<Item = &'a Org>, query: &AgendaQuery) -> Vec<AgendaDay> {
+    let mut days: BTreeMap<NaiveDate, Vec<AgendaItem>> = query
+        .from
+        .iter_days()
+        .take_while(|d| *d <= query.to)
+        .map(|d| (d, vec![]))
+        .collect();
+
+    for org in orgs {
+        let tags = org.file_tags();
+        for sec in &org.sections {
+            collect(org, sec, &tags, query, &mut days);
+        }
+    }
+
+    days.into_iter()
+        .map(|(date, mut items)| {
+            items.sort_by(|a, b| {
+                // timed items first, then by priority like org-agenda
+                (
+                    a.time.is_none(),
+                    a.time,
+                    a.priority.unwrap_or('B'),
+                    a.kind,
+                    &a.title,
+                )
+                    .cmp(&(
+                        b.time.is_none(),
+                        b.time,
+                        b.priority.unwrap_or('B'),
+                        b.kind,
+                        &b.title,
+                    ))
+            });
+            AgendaDay { date, items }
+        })
+        .collect()
+}
+
+fn collect(
+    org: &Org,
+    sec: &Section,
+    inherited: &[String],
+    query: &AgendaQuery,
+    days: &mut BTreeMap<NaiveDate, Vec<AgendaItem>>,
+) {
+    let mut tags = inherited.to_vec();
+    for tag in &sec.tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+
+    let matches = query.tags.iter().all(|t| tags.contains(t))
+        && (query.todo.is_empty()
+            || sec
+                .todo
+                .as_ref()
+                .is_some_and(|todo| query.todo.contains(todo)));
+    if matches {
+        for sch in &sec.scheduling {
+            let ts = sch.timestamp();
+            if !ts.active {
+                continue;
+            }
+            let kind = match sch {
+                Scheduling::Scheduled(_) => AgendaKind::Scheduled,
+                Scheduling::Deadline(_) => AgendaKind::Deadline,
+            };
+            let item = |date: NaiveDate, days: i64| AgendaItem {
+                kind,
+                title: sec.title.clone(),
+                todo: sec.todo.clone(),
+                done: sec.done,
+                priority: sec.priority,
+                tags: tags.clone(),
+                file: org.filename.clone(),
+                line: sec.line,
+                id: sec.property("ID").map(|id| id.to_string()),
+                date,
+                time: ts.time,
+                end_time: ts.end_time,
+                days,
+                timestamp: ts.to_string(),
+            };
+
+            let dates = occurrences(ts, query.from, query.to);
+            for date in &dates {
+                if let Some(items) = days.get_mut(date) {
+                    items.push(item(*date, 0));
+                }
+            }
+
+            // open items that are overdue or whose deadline is near show up today,
+            // unless an occurrence already does
+            if sec.done || dates.contains(&query.today) {
+                continue;
+            }
+            let Some(items) = days.get_mut(&query.today) else {
+                continue;
+            };
+            let diff = (ts.date - query.today).num_days();
+            let shown = match kind {
+                AgendaKind::Scheduled => diff < 0,
+                AgendaKind::Deadline => diff < 0 || warning_start(ts, query) <= query.today,
+            };
+            if shown {
+                items.push(item(ts.date, diff));
+            }
+        }
+    }
+
+    for child in &sec.sections {
+        collect(org, child, &tags, query, days);
+    }
+}
+
+/// Dates in `from..=to` the timestamp falls on, following its repeater.
+fn occurrences(ts: &Timestamp, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    let mut dates = vec![];
+    let Some(mut after) = from
+        .and_time(NaiveTime::MIN)
+        .checked_sub_signed(chrono::Duration::seconds(1))
+    else {
+        return dates;
+    };
+    while let Some(dt) = ts.next_occurrence(NaiveTime::MIN, after) {
+        if dt.date() > to {
+            break;
+        }
+        if dates.last() != Some(&dt.date()) {
+            dates.push(dt.date());
+        }
+        after = dt;
+    }
+    dates
+}
+
+/// First day a deadline is shown ahead of time.
+fn warning_start(ts: &Timestamp, query: &AgendaQuery) -> NaiveDate {
+    let interval = ts.warning.map(|w| w.interval).unwrap_or(Interval {
+        value: query.deadline_warning_days,
+        unit: TimeUnit::Day,
+    });
+    interval
+        .before(ts.date.and_time(NaiveTime::MIN))
+        .map(|dt| dt.date())
+        .unwrap_or(ts.date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Context};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%F").unwrap()
+    }
+
+    fn query() -> AgendaQuery {
+        AgendaQuery {
+            from: date("2024-03-04"),
+            to: date("2024-03-10"),
+            today: date("2024-03-05"),
+            deadline_warning_days: 14,
+            tags: vec![],
+            todo: vec![],
+        }
+    }
+
+    const CONTENT: &str = r#"#+FILETAGS: :home:
+* TODO Weekly review :work:
+SCHEDULED: <2024-03-01 Fri 16:00 +3d>
+* TODO [#A] Pay rent
+DEADLINE: <2024-03-20 Wed -5d>
+* TODO Tax return
+DEADLINE: <2024-03-12 Tue>
+* DONE Call Bob
+SCHEDULED: <2024-03-01 Fri>
+* TODO Overdue
+SCHEDULED: <2024-03-02 Sat 09:00>
+"#;
+
+    #[test]
+    fn test_agenda() {
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, CONTENT).unwrap();
+        let days = agenda(&[org], &query());
+        assert_eq!(7, days.len());
+        assert_eq!(date("2024-03-04"), days[0].date);
+        let titles = |d: &AgendaDay| d.items.iter().map(|i| i.title.clone()).collect::<Vec<_>>();
+
+        assert_eq!(vec!["Weekly review"], titles(&days[0]));
+        // overdue items and near deadlines are listed today
+        assert_eq!(
+            vec!["Overdue", "Weekly review", "Tax return"],
+            titles(&days[1])
+        );
+        assert_eq!(-3, days[1].items[0].days);
+        assert_eq!(-4, days[1].items[1].days);
+        assert_eq!(7, days[1].items[2].days);
+        assert_eq!(vec!["Weekly review"], titles(&days[3]));
+        assert_eq!(vec!["home", "work"], days[3].items[0].tags);
+        assert!(days[5].items.is_empty());
+        assert_eq!(vec!["Weekly review"], titles(&days[6]));
+    }
+
+    #[test]
+    fn test_agenda_filter() {
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, CONTENT).unwrap();
+        let mut query = query();
+        query.tags = vec!["work".to_string()];
+        let days = agenda(std::slice::from_ref(&org), &query);
+        let n: usize = days.iter().map(|d| d.items.len()).sum();
+        assert_eq!(4, n);
+
+        query.tags = vec![];
+        query.todo = vec!["DONE".to_string()];
+        query.from = date("2024-03-01");
+        let days = agenda(&[org], &query);
+        assert_eq!("Call Bob", days[0].items[0].title);
+        let n: usize = days.iter().map(|d| d.items.len()).sum();
+        assert_eq!(1, n);
+    }
+
+    #[test]
+    fn test_agenda_repeated_today() {
+        let content = "* TODO Water plants\nSCHEDULED: <2024-02-27 Tue +1w>\n* TODO Report\nDEADLINE: <2024-02-20 Tue +2w>\n";
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap();
+        let days = agenda(&[org], &query());
+
+        // both repeat onto today, which lists them once as occurrences, not as overdue
+        let today: Vec<_> = days[1]
+            .items
+            .iter()
+            .map(|i| (i.title.as_str(), i.days))
+            .collect();
+        assert_eq!(vec![("Report", 0), ("Water plants", 0)], today);
+        let n: usize = days.iter().map(|d| d.items.len()).sum();
+        assert_eq!(2, n);
+    }
+}
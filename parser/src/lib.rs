@@ -1,8 +1,18 @@
+mod agenda;
 mod parser;
+mod query;
 mod reminder;
+mod timestamp;
 
+pub use agenda::{agenda, AgendaDay, AgendaItem, AgendaKind, AgendaQuery};
 pub use parser::parse;
 pub use parser::Context;
 pub use parser::Org;
 pub use parser::OrgParser;
-pub use reminder::Reminder;
+pub use parser::Scheduling;
+pub use parser::Section;
+pub use parser::Span;
+pub use parser::TodoKeywords;
+pub use query::{query, Query, QueryItem};
+pub use reminder::{parse_offsets, Reminder, ReminderKind, ReminderOptions};
+pub use timestamp::{Interval, Repeater, RepeaterKind, TimeUnit, Timestamp, Warning, WarningKind};
@@ -1,5 +1,7 @@
-use crate::{reminder::get_reminders, Reminder};
+use crate::reminder::{get_reminders, parse_offsets, REMINDERS_KEY};
+use crate::{Reminder, ReminderOptions, Timestamp};
 use anyhow::Result;
+use chrono::{Local, NaiveDateTime};
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
@@ -11,12 +13,130 @@ use tracing::debug;
 #[grammar = "org.pest"]
 pub struct OrgParser;
 
-#[derive(Clone, Default)]
-pub struct Context {} // TODO add attr
+/// One TODO keyword sequence, as written in `#+TODO: TODO WAITING | DONE CANCELLED`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TodoKeywords {
+    pub todo: Vec<String>,
+    pub done: Vec<String>,
+}
+
+impl TodoKeywords {
+    /// Parse a sequence, ignoring fast access keys such as `TODO(t)`.
+    /// Without a `|`, the last keyword is the done state.
+    pub fn parse(value: &str) -> Self {
+        let mut todo = vec![];
+        let mut done = vec![];
+        let mut seen_sep = false;
+        for word in value.split_whitespace() {
+            if word == "|" {
+                seen_sep = true;
+                continue;
+            }
+            let word = word.split('(').next().unwrap_or(word).to_string();
+            if word.is_empty() {
+                continue;
+            }
+            if seen_sep {
+                done.push(word);
+            } else {
+                todo.push(word);
+            }
+        }
+        if !seen_sep {
+            if let Some(last) = todo.pop() {
+                done.push(last);
+            }
+        }
+        TodoKeywords { todo, done }
+    }
+
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.todo
+            .iter()
+            .chain(self.done.iter())
+            .any(|k| k == keyword)
+    }
+}
+
+impl Default for TodoKeywords {
+    fn default() -> Self {
+        TodoKeywords::parse("TODO DOING | DONE")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Context {
+    /// Active TODO keyword sequences, replaced by `#+TODO` lines of the file.
+    pub todo_keywords: Vec<TodoKeywords>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            todo_keywords: vec![TodoKeywords::default()],
+        }
+    }
+}
 
 impl Context {
     pub fn new() -> Self {
-        Context {}
+        Default::default()
+    }
+
+    pub fn with_todo_keywords(todo_keywords: Vec<TodoKeywords>) -> Self {
+        Context { todo_keywords }
+    }
+
+    pub fn is_todo_keyword(&self, keyword: &str) -> bool {
+        self.todo_keywords.iter().any(|seq| seq.contains(keyword))
+    }
+
+    pub fn is_done(&self, keyword: &str) -> bool {
+        self.todo_keywords
+            .iter()
+            .any(|seq| seq.done.iter().any(|k| k == keyword))
+    }
+
+    /// Split the keyword and priority cookie off the headline according to the active keywords.
+    fn resolve_todo(&self, section: &mut Section) {
+        if let Some(todo) = section.todo.take() {
+            if self.is_todo_keyword(&todo) {
+                section.todo = Some(todo);
+            } else {
+                // not a keyword in this file, it is part of the title
+                let mut words = vec![todo];
+                if let Some(p) = section.priority.take() {
+                    words.push(format!("[#{}]", p));
+                }
+                if !section.title.is_empty() {
+                    words.push(section.title.clone());
+                }
+                section.title = words.join(" ");
+            }
+        }
+
+        if section.todo.is_none() {
+            let title = section.title.clone();
+            let (first, rest) = title.split_once(' ').unwrap_or((&title, ""));
+            if self.is_todo_keyword(first) {
+                section.todo = Some(first.to_string());
+                let mut rest = rest.trim_start();
+                if section.priority.is_none() {
+                    let cookie = rest.strip_prefix("[#").and_then(|r| {
+                        let mut chars = r.chars();
+                        let p = chars.next().filter(char::is_ascii_alphanumeric)?;
+                        chars.as_str().strip_prefix(']').map(|r| (p, r))
+                    });
+                    if let Some((p, r)) = cookie {
+                        section.priority = Some(p);
+                        rest = r.trim_start();
+                    }
+                }
+                section.title = rest.to_string();
+            }
+        }
+
+        section.done = section.todo.as_deref().is_some_and(|k| self.is_done(k));
     }
 }
 
@@ -29,6 +149,8 @@ pub struct Org {
     pub properties: Vec<Properties>,
     pub keywords: Vec<Keyword>,
     pub sections: Vec<Section>,
+    /// TODO keyword sequences in effect for this file.
+    pub todo_keywords: Vec<TodoKeywords>,
 }
 
 impl Org {
@@ -41,33 +163,114 @@ impl Org {
             properties: Vec::new(),
             keywords: Vec::new(),
             sections: Vec::new(),
+            todo_keywords: Vec::new(),
         }
     }
 
-    pub fn get_reminders(&self) -> Vec<Reminder> {
+    /// Value of a file-level `#+KEY:` keyword, matched case-insensitively.
+    pub fn keyword(&self, key: &str) -> Option<&str> {
+        self.keywords
+            .iter()
+            .find(|kw| kw.key.eq_ignore_ascii_case(key))
+            .map(|kw| kw.value.as_str())
+    }
+
+    /// Tags set with `#+FILETAGS`, inherited by every heading.
+    pub fn file_tags(&self) -> Vec<String> {
+        self.keyword("FILETAGS")
+            .map(|v| {
+                v.split(':')
+                    .filter(|t| !t.trim().is_empty())
+                    .map(|t| t.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Value of a file-level property, matched case-insensitively.
+    pub fn property(&self, key: &str) -> Option<&str> {
+        find_property(&self.properties, key)
+    }
+
+    /// The heading starting on `line`, at any depth.
+    pub fn section_at(&self, line: usize) -> Option<&Section> {
+        find_section(&self.sections, line)
+    }
+
+    pub fn get_reminders(&self, opts: &ReminderOptions) -> Vec<Reminder> {
+        self.get_reminders_after(opts, Local::now().naive_local())
+    }
+
+    /// Reminders for the first occurrence of each item after `now`, including the
+    /// ones whose notification time is already past.
+    pub fn get_reminders_after(&self, opts: &ReminderOptions, now: NaiveDateTime) -> Vec<Reminder> {
+        let offsets = self
+            .property(REMINDERS_KEY)
+            .or_else(|| self.keyword(REMINDERS_KEY))
+            .and_then(|value| match parse_offsets(value) {
+                Ok(offsets) => Some(offsets),
+                Err(err) => {
+                    debug!("ignore {}: {:?}", REMINDERS_KEY, err);
+                    None
+                }
+            });
+        let offsets = offsets.as_deref().unwrap_or(&opts.offsets);
+
         let mut res = vec![];
         for sec in &self.sections {
-            let mut reminders = get_reminders(sec);
+            let mut reminders = get_reminders(sec, now, opts, offsets);
             if !reminders.is_empty() {
                 res.append(&mut reminders)
             }
         }
+        for r in &mut res {
+            r.file.clone_from(&self.filename);
+        }
         res
     }
 }
 
+/// Byte range of a node in the parsed text, so edits can be spliced into it.
+///
+/// A span covers exactly what the node's grammar rule matched, line endings it
+/// consumed included, so `&text[span.range()]` is the node's source.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        Span {
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Keyword {
     pub key: String,
     pub value: String,
     pub col: usize,
     pub line: usize,
+    pub span: Span,
 }
 
+/// A property drawer.
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Properties {
     pub col: usize,
     pub line: usize,
+    /// From `:PROPERTIES:` to `:END:` and the blank lines after it.
+    pub span: Span,
     pub children: Vec<Property>,
 }
 
@@ -77,6 +280,7 @@ pub struct Property {
     pub value: String,
     pub col: usize,
     pub line: usize,
+    pub span: Span,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -84,6 +288,7 @@ pub struct Drawer {
     pub name: String,
     pub col: usize,
     pub line: usize,
+    pub span: Span,
     pub children: Vec<Content>,
 }
 
@@ -91,6 +296,8 @@ pub struct Drawer {
 pub struct Content {
     pub col: usize,
     pub line: usize,
+    /// The bytes of `contents`.
+    pub span: Span,
     pub contents: String,
 }
 
@@ -98,7 +305,21 @@ pub struct Content {
 pub struct Section {
     pub col: usize,
     pub line: usize,
+    /// The whole subtree, up to where the next heading of the same or a higher level starts.
+    pub span: Span,
+    /// The headline without its newline.
+    pub headline: Span,
+    /// The `SCHEDULED:` and `DEADLINE:` entries on the line right after the headline,
+    /// without its newline. Entries further down are in `scheduling` only.
+    pub planning: Option<Span>,
+    /// Number of stars of the headline.
+    pub level: usize,
+    pub todo: Option<String>,
+    /// Whether `todo` is a done state.
+    pub done: bool,
+    pub priority: Option<char>,
     pub title: String,
+    pub tags: Vec<String>,
     pub drawers: Vec<Drawer>,
     pub properties: Vec<Properties>,
     pub keywords: Vec<Keyword>,
@@ -107,10 +328,40 @@ pub struct Section {
     pub scheduling: Vec<Scheduling>,
 }
 
+impl Section {
+    /// Value of a property in the section's drawer, matched case-insensitively.
+    pub fn property(&self, key: &str) -> Option<&str> {
+        find_property(&self.properties, key)
+    }
+
+    pub fn scheduled(&self) -> Option<&Timestamp> {
+        self.scheduling.iter().find_map(|sch| match sch {
+            Scheduling::Scheduled(ts) => Some(ts),
+            _ => None,
+        })
+    }
+
+    pub fn deadline(&self) -> Option<&Timestamp> {
+        self.scheduling.iter().find_map(|sch| match sch {
+            Scheduling::Deadline(ts) => Some(ts),
+            _ => None,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq)]
 pub enum Scheduling {
-    Scheduled(String),
-    Deadline(String),
+    Scheduled(Timestamp),
+    Deadline(Timestamp),
+}
+
+impl Scheduling {
+    pub fn timestamp(&self) -> &Timestamp {
+        match self {
+            Scheduling::Scheduled(ts) => ts,
+            Scheduling::Deadline(ts) => ts,
+        }
+    }
 }
 
 impl PartialEq for Scheduling {
@@ -138,14 +389,36 @@ impl Hash for Scheduling {
     }
 }
 
+fn find_section(sections: &[Section], line: usize) -> Option<&Section> {
+    sections.iter().find_map(|sec| {
+        if sec.line == line {
+            Some(sec)
+        } else {
+            find_section(&sec.sections, line)
+        }
+    })
+}
+
+fn find_property<'a>(properties: &'a [Properties], key: &str) -> Option<&'a str> {
+    properties
+        .iter()
+        .flat_map(|props| props.children.iter())
+        .find(|prop| prop.key.eq_ignore_ascii_case(key))
+        .map(|prop| prop.value.as_str())
+}
+
 fn parse_properties(_ctx: &mut Context, pair: Pair<'_, Rule>) -> Properties {
     let mut properties: Properties = Default::default();
     let (line, col) = pair.line_col();
     properties.line = line;
     properties.col = col;
+    properties.span = pair.as_span().into();
 
     for pair in pair.into_inner() {
-        let mut prop: Property = Default::default();
+        let mut prop = Property {
+            span: pair.as_span().into(),
+            ..Default::default()
+        };
 
         for pair in pair.into_inner() {
             match pair.as_rule() {
@@ -171,6 +444,7 @@ fn parse_drawer(_ctx: &mut Context, pair: Pair<'_, Rule>) -> Drawer {
     let (line, col) = pair.line_col();
     drawer.line = line;
     drawer.col = col;
+    drawer.span = pair.as_span().into();
 
     for pair in pair.into_inner() {
         match pair.as_rule() {
@@ -179,16 +453,14 @@ fn parse_drawer(_ctx: &mut Context, pair: Pair<'_, Rule>) -> Drawer {
             }
             Rule::drawer_contents => {
                 for pair in pair.into_inner() {
-                    match pair.as_rule() {
-                        Rule::drawer_content => {
-                            let mut content: Content = Default::default();
-                            let (line, col) = pair.line_col();
-                            content.line = line;
-                            content.col = col;
-                            content.contents = pair.as_str().to_string();
-                            drawer.children.push(content);
-                        }
-                        _ => {}
+                    if pair.as_rule() == Rule::drawer_content {
+                        let mut content: Content = Default::default();
+                        let (line, col) = pair.line_col();
+                        content.line = line;
+                        content.col = col;
+                        content.span = pair.as_span().into();
+                        content.contents = pair.as_str().to_string();
+                        drawer.children.push(content);
                     }
                 }
             }
@@ -199,7 +471,10 @@ fn parse_drawer(_ctx: &mut Context, pair: Pair<'_, Rule>) -> Drawer {
 }
 
 fn parse_keyword(_ctx: &mut Context, pair: Pair<'_, Rule>) -> Keyword {
-    let mut kw: Keyword = Default::default();
+    let mut kw = Keyword {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for pair in pair.into_inner() {
         match pair.as_rule() {
@@ -225,27 +500,35 @@ fn parse_section(ctx: &mut Context, pair: Pair<'_, Rule>) -> Section {
     let (line, col) = pair.line_col();
     section.col = col;
     section.line = line;
+    section.span = pair.as_span().into();
 
     for pair in pair.into_inner() {
         match pair.as_rule() {
             Rule::headline => {
-                let hl = pair.as_str();
-                if let Ok(parsed) = OrgParser::parse(Rule::headline, hl) {
-                    for pair in parsed {
-                        for pair in pair.into_inner() {
-                            match pair.as_rule() {
-                                Rule::headline_symbol => {}
-                                Rule::headline_title => {
-                                    section.title = pair.as_str().to_string();
-                                }
-                                Rule::tags => {
-                                    // TODO
-                                }
-                                _ => {}
+                section.headline = pair.as_span().into();
+                for pair in pair.into_inner() {
+                    match pair.as_rule() {
+                        Rule::headline_symbol => {
+                            section.level = pair.as_str().len();
+                        }
+                        Rule::todo_status => {
+                            section.todo = Some(pair.as_str().to_string());
+                        }
+                        Rule::priority => {
+                            section.priority = pair.as_str().chars().nth(2);
+                        }
+                        Rule::headline_title => {
+                            section.title = pair.as_str().trim_end().to_string();
+                        }
+                        Rule::tags => {
+                            for pair in pair.into_inner() {
+                                section.tags.push(pair.as_str().to_string());
                             }
                         }
+                        _ => {}
                     }
                 }
+                ctx.resolve_todo(&mut section);
             }
             Rule::properties => {
                 let prop = parse_properties(ctx, pair);
@@ -260,23 +543,33 @@ fn parse_section(ctx: &mut Context, pair: Pair<'_, Rule>) -> Section {
                 section.keywords.push(kw);
             }
             Rule::scheduling => {
+                let span: Span = pair.as_span().into();
+                match &mut section.planning {
+                    // the next entry on the planning line
+                    Some(planning) if planning.end == span.start => planning.end = span.end,
+                    None if pair.line_col().0 == section.line + 1 => {
+                        section.planning = Some(span);
+                    }
+                    _ => {}
+                }
                 for pair in pair.into_inner() {
-                    match pair.as_rule() {
+                    let rule = pair.as_rule();
+                    let Some(pair) = pair.into_inner().next() else {
+                        continue;
+                    };
+                    let ts = match Timestamp::from_pair(pair) {
+                        Ok(ts) => ts,
+                        Err(err) => {
+                            debug!("invalid timestamp {:?}", err);
+                            continue;
+                        }
+                    };
+                    match rule {
                         Rule::scheduled => {
-                            if let Some(pair) = pair.into_inner().next() {
-                                if let Some(pair) = pair.into_inner().next() {
-                                    let sch = Scheduling::Scheduled(pair.as_str().to_string());
-                                    section.scheduling.push(sch);
-                                }
-                            }
+                            section.scheduling.push(Scheduling::Scheduled(ts));
                         }
                         Rule::deadline => {
-                            if let Some(pair) = pair.into_inner().next() {
-                                if let Some(pair) = pair.into_inner().next() {
-                                    let sch = Scheduling::Deadline(pair.as_str().to_string());
-                                    section.scheduling.push(sch);
-                                }
-                            }
+                            section.scheduling.push(Scheduling::Deadline(ts));
                         }
                         _ => {}
                     }
@@ -287,6 +580,7 @@ fn parse_section(ctx: &mut Context, pair: Pair<'_, Rule>) -> Section {
                 let (line, col) = pair.line_col();
                 content.col = col;
                 content.line = line;
+                content.span = pair.as_span().into();
                 content.contents = pair.as_str().to_string();
                 section.contents.push(content);
             }
@@ -303,8 +597,20 @@ fn parse_section(ctx: &mut Context, pair: Pair<'_, Rule>) -> Section {
     section
 }
 
+/// Append `sec` as the last child of the deepest preceding section with a lower level.
+fn push_section(sections: &mut Vec<Section>, sec: Section) {
+    match sections.last_mut() {
+        Some(parent) if parent.level < sec.level => {
+            parent.span.end = parent.span.end.max(sec.span.end);
+            push_section(&mut parent.sections, sec)
+        }
+        _ => sections.push(sec),
+    }
+}
+
 pub fn parse(ctx: &mut Context, content: &str) -> Result<Org> {
     let mut org = Org::default();
+    let mut file_keywords = false;
     let mut pairs = OrgParser::parse(Rule::org, content)?;
     if let Some(pair) = pairs.next() {
         for pair in pair.into_inner() {
@@ -324,14 +630,23 @@ pub fn parse(ctx: &mut Context, content: &str) -> Result<Org> {
                 }
                 Rule::keyword => {
                     let kw = parse_keyword(ctx, pair);
-                    if kw.key.to_lowercase() == "title" {
+                    let key = kw.key.to_lowercase();
+                    if key == "title" {
                         org.title = Some(kw.value.to_string());
                     }
+                    if key == "todo" || key == "seq_todo" || key == "typ_todo" {
+                        // keywords of the file replace the defaults
+                        if !file_keywords {
+                            ctx.todo_keywords.clear();
+                            file_keywords = true;
+                        }
+                        ctx.todo_keywords.push(TodoKeywords::parse(&kw.value));
+                    }
                     org.keywords.push(kw);
                 }
                 Rule::section => {
                     let sec = parse_section(ctx, pair); // TODO parse src block
-                    org.sections.push(sec);
+                    push_section(&mut org.sections, sec);
                 }
                 _ => {
                     debug!("! {:?}", pair);
@@ -340,12 +655,14 @@ pub fn parse(ctx: &mut Context, content: &str) -> Result<Org> {
         }
     }
 
+    org.todo_keywords = ctx.todo_keywords.clone();
     Ok(org)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::NaiveTime;
     use pest::Parser;
 
     fn init() {
@@ -363,6 +680,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rule_timestamp() {
+        init();
+        let content = "<2023-12-11 Mon 07:09-08:00 +1w -2d>";
+        let pairs =
+            OrgParser::parse(Rule::active_timestamp, content).unwrap_or_else(|e| panic!("{}", e));
+        for pair in pairs {
+            assert_eq!(content, pair.as_str());
+            for pair in pair.into_inner() {
+                match pair.as_rule() {
+                    Rule::ts_date => assert_eq!("2023-12-11", pair.as_str()),
+                    Rule::ts_dayname => assert_eq!("Mon", pair.as_str()),
+                    Rule::ts_time => assert_eq!("07:09", pair.as_str()),
+                    Rule::ts_end_time => assert_eq!("08:00", pair.as_str()),
+                    Rule::ts_repeater => assert_eq!("+1w", pair.as_str()),
+                    Rule::ts_warning => assert_eq!("-2d", pair.as_str()),
+                    rule => panic!("unexpected rule {:?}", rule),
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_rule_scheduled() {
         init();
@@ -371,7 +710,7 @@ mod tests {
         for pair in pairs {
             assert_eq!(Rule::scheduled, pair.as_rule());
             for pair in pair.into_inner() {
-                assert_eq!(Rule::active_time_quoted, pair.as_rule());
+                assert_eq!(Rule::active_timestamp, pair.as_rule());
                 // println!("{:?}", pair);
             }
         }
@@ -393,7 +732,7 @@ mod tests {
         init();
         let content = "[2023-12-11 Mon 07:09]";
         let pairs =
-            OrgParser::parse(Rule::inactive_quoted, content).unwrap_or_else(|e| panic!("{}", e));
+            OrgParser::parse(Rule::inactive_timestamp, content).unwrap_or_else(|e| panic!("{}", e));
         for pair in pairs {
             println!("{:?}", pair);
         }
@@ -493,6 +832,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rule_property_with_spaces() {
+        init();
+        let content = ":REMINDERS: 2h 15m 0m  ";
+        let pairs = OrgParser::parse(Rule::property, content).unwrap_or_else(|e| panic!("{}", e));
+        for pair in pairs {
+            for pair in pair.into_inner() {
+                if pair.as_rule() == Rule::property_value {
+                    assert_eq!("2h 15m 0m", pair.as_str());
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_rule_property_timestamp() {
         init();
@@ -994,8 +1347,8 @@ Content2
 #+STARTUP: overview
 
 * SECTION 1
-SCHEDULED: <2024-12-03 Tue 12:34>
-DEADLINE: <2024-12-03 Tue 10:30>
+SCHEDULED: <2099-12-03 Thu 12:34>
+DEADLINE: <2099-12-03 Thu 10:30>
 #+KEYWORD1: title1
 :PROPERTIES:
 :ID: 461e7f4a-5467-4e1b-baed-517a02c00b9c
@@ -1022,9 +1375,304 @@ CONTENT1
         let sec = org.sections.first().unwrap();
 
         assert_eq!(1, sec.drawers.len());
+        assert_eq!(2, sec.scheduling.len());
+        let scheduled = sec.scheduled().unwrap();
+        assert_eq!("<2099-12-03 Thu 12:34>", scheduled.to_string());
+        let deadline = sec.deadline().unwrap();
+        assert_eq!(NaiveTime::from_hms_opt(10, 30, 0), deadline.time);
+
+        let rems = org.get_reminders(&ReminderOptions::default());
+        // 3 offsets for the scheduled item, 3 offsets and 14 daily warnings for the deadline
+        assert_eq!(20, rems.len());
+    }
+
+    #[test]
+    fn test_reminder_offsets() {
+        init();
+
+        let content = r#"#+TITLE: title
+#+REMINDERS: 1h
+
+* SECTION 1
+SCHEDULED: <2099-12-03 Thu 12:34>
+
+* SECTION 2
+SCHEDULED: <2099-12-03 Thu 12:34>
+:PROPERTIES:
+:REMINDERS: 2h 15m 0m
+:END:
+
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let rems = org.get_reminders(&ReminderOptions::default());
+        let mut offsets: Vec<_> = rems.iter().map(|r| r.at - r.datetime).collect();
+        offsets.sort();
+        assert_eq!(
+            vec![
+                chrono::Duration::zero(),
+                chrono::Duration::minutes(15),
+                chrono::Duration::hours(1),
+                chrono::Duration::hours(2),
+            ],
+            offsets
+        );
+    }
+
+    #[test]
+    fn test_huge_reminder_offset() {
+        init();
+
+        let content = r#"* SECTION 1
+SCHEDULED: <2099-12-03 Thu 12:34>
+:PROPERTIES:
+:REMINDERS: 99999999999w 1h
+:END:
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let rems = org.get_reminders(&ReminderOptions::default());
+        let offsets: Vec<_> = rems.iter().map(|r| r.at - r.datetime).collect();
+        assert_eq!(vec![chrono::Duration::hours(1)], offsets);
+
+        assert!(parse_offsets("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_reminders_after() {
+        init();
+
+        let content = r#"* Past
+SCHEDULED: <2024-03-04 Mon 10:00>
+* Repeated
+SCHEDULED: <2024-03-04 Mon 10:00 +1d>
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let opts = ReminderOptions::default();
+        let since = NaiveDateTime::parse_from_str("2024-03-05 09:45", "%F %R").unwrap();
+        let rems = org.get_reminders_after(&opts, since);
+        assert_eq!(3, rems.len());
+        assert!(rems.iter().all(|r| r.title == "Repeated"));
+        assert_eq!(
+            NaiveDateTime::parse_from_str("2024-03-05 10:00", "%F %R").unwrap(),
+            rems[0].at
+        );
+
+        let since = NaiveDateTime::parse_from_str("2024-03-04 09:45", "%F %R").unwrap();
+        assert_eq!(6, org.get_reminders_after(&opts, since).len());
+    }
+
+    #[test]
+    fn test_parse_headline() {
+        init();
+
+        let content = r#"* TODO [#A] Write report :work:urgent:
+** DONE Draft
+*** Notes at 10:30:00
+Some *bold* text
+ * not a headline
+** Review
+* Todo list
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(2, org.sections.len());
+        let sec = &org.sections[0];
+        assert_eq!(1, sec.level);
+        assert_eq!(Some("TODO".to_string()), sec.todo);
+        assert_eq!(Some('A'), sec.priority);
+        assert_eq!("Write report", sec.title);
+        assert_eq!(vec!["work", "urgent"], sec.tags);
+        assert_eq!(2, sec.sections.len());
+
+        let draft = &sec.sections[0];
+        assert_eq!(2, draft.level);
+        assert_eq!(Some("DONE".to_string()), draft.todo);
+        assert_eq!(None, draft.priority);
+        assert_eq!("Draft", draft.title);
+
+        let notes = &draft.sections[0];
+        assert_eq!(3, notes.level);
+        assert_eq!("Notes at 10:30:00", notes.title);
+        assert!(notes.tags.is_empty());
+        assert_eq!(
+            "Some *bold* text\n * not a headline\n",
+            notes.contents[0].contents
+        );
+
+        assert_eq!("Review", sec.sections[1].title);
+
+        let sec = &org.sections[1];
+        assert_eq!(None, sec.todo);
+        assert_eq!("Todo list", sec.title);
+    }
+
+    #[test]
+    fn test_spans() {
+        init();
+
+        let content = r#"#+TITLE: Spans
+* TODO Parent :work:
+SCHEDULED: <2024-03-04 Mon>  DEADLINE: <2024-03-08 Fri>
+:PROPERTIES:
+:ID:       parent
+:END:
+Body text  
+** Child
+:LOGBOOK:
+- Note taken
+:END:
+* Next
+"#;
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let text = |span: Span| &content[span.range()];
+
+        assert_eq!("#+TITLE: Spans", text(org.keywords[0].span));
+        let sec = &org.sections[0];
+        assert_eq!("* TODO Parent :work:", text(sec.headline));
+        let stamps: Vec<_> = sec
+            .scheduling
+            .iter()
+            .map(|sch| text(sch.timestamp().span))
+            .collect();
+        assert_eq!(vec!["<2024-03-04 Mon>", "<2024-03-08 Fri>"], stamps);
+        assert_eq!(
+            "SCHEDULED: <2024-03-04 Mon>  DEADLINE: <2024-03-08 Fri>",
+            text(sec.planning.unwrap())
+        );
+        assert_eq!(
+            ":PROPERTIES:\n:ID:       parent\n:END:\n",
+            text(sec.properties[0].span)
+        );
+        assert_eq!(
+            ":ID:       parent",
+            text(sec.properties[0].children[0].span)
+        );
+        assert_eq!(sec.contents[0].contents, text(sec.contents[0].span));
+        assert_eq!("Body text  \n", text(sec.contents[0].span));
+        // the subtree runs up to the next heading of the same level
+        assert!(text(sec.span).starts_with("* TODO Parent"));
+        assert!(text(sec.span).ends_with(":END:\n"));
+        let child = &sec.sections[0];
+        assert!(child.scheduling.is_empty());
+        assert_eq!(
+            ":LOGBOOK:\n- Note taken\n:END:\n",
+            text(child.drawers[0].span)
+        );
+        assert_eq!(sec.span.end, child.span.end);
+        assert_eq!("* Next\n", text(org.sections[1].span));
+        assert!(org.sections[1].planning.is_none());
+
+        // entries after a drawer are not on the planning line
+        let content = "* Task\nSCHEDULED: <2024-03-04 Mon>\n:PROPERTIES:\n:ID: task\n:END:\nDEADLINE: <2024-03-08 Fri>\n* Later\n:PROPERTIES:\n:ID: later\n:END:\nSCHEDULED: <2024-03-04 Mon>\n";
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let text = |span: Span| &content[span.range()];
+        assert_eq!(2, org.sections[0].scheduling.len());
+        assert_eq!(
+            "SCHEDULED: <2024-03-04 Mon>",
+            text(org.sections[0].planning.unwrap())
+        );
+        assert_eq!(1, org.sections[1].scheduling.len());
+        assert!(org.sections[1].planning.is_none());
+    }
 
-        let rems = org.get_reminders();
-        assert_eq!(6, rems.len());
+    #[test]
+    fn test_todo_keywords() {
+        init();
+
+        let kw = TodoKeywords::parse("TODO(t) WAITING(w@/!) | DONE(d) CANCELLED(c)");
+        assert_eq!(vec!["TODO", "WAITING"], kw.todo);
+        assert_eq!(vec!["DONE", "CANCELLED"], kw.done);
+        let kw = TodoKeywords::parse("NEXT LATER FINISHED");
+        assert_eq!(vec!["NEXT", "LATER"], kw.todo);
+        assert_eq!(vec!["FINISHED"], kw.done);
+
+        let content = r#"#+TODO: TODO WAITING | DONE CANCELLED
+
+* WAITING [#B] Reply from vendor
+* CANCELLED Party
+* DOING Not a keyword here
+* TODO
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(4, org.sections.len());
+
+        let sec = &org.sections[0];
+        assert_eq!(Some("WAITING".to_string()), sec.todo);
+        assert_eq!(Some('B'), sec.priority);
+        assert_eq!("Reply from vendor", sec.title);
+        assert!(!sec.done);
+
+        let sec = &org.sections[1];
+        assert_eq!(Some("CANCELLED".to_string()), sec.todo);
+        assert!(sec.done);
+
+        let sec = &org.sections[2];
+        assert_eq!(None, sec.todo);
+        assert_eq!("DOING Not a keyword here", sec.title);
+
+        let sec = &org.sections[3];
+        assert_eq!(Some("TODO".to_string()), sec.todo);
+        assert_eq!("", sec.title);
+
+        // keywords from the configuration
+        let mut ctx = Context::with_todo_keywords(vec![TodoKeywords::parse("NEXT | FINISHED")]);
+        let org = parse(&mut ctx, "* FINISHED Task\n").unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(Some("FINISHED".to_string()), org.sections[0].todo);
+        assert!(org.sections[0].done);
+    }
+
+    #[test]
+    fn test_skip_done_reminders() {
+        init();
+
+        let content = r#"* DONE Finished
+SCHEDULED: <2099-12-03 Thu 12:34>
+** TODO Follow up
+SCHEDULED: <2099-12-04 Fri 12:34>
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let rems = org.get_reminders(&ReminderOptions::default());
+        assert_eq!(3, rems.len());
+        assert!(rems.iter().all(|r| r.title == "Follow up"));
+
+        let opts = ReminderOptions {
+            skip_done: false,
+            ..Default::default()
+        };
+        assert_eq!(6, org.get_reminders(&opts).len());
+    }
+
+    #[test]
+    fn test_inherited_reminder_offsets() {
+        init();
+
+        let content = r#"* Project
+:PROPERTIES:
+:REMINDERS: 5m
+:END:
+** Meeting :work:
+SCHEDULED: <2099-12-03 Thu 12:34>
+"#;
+
+        let mut ctx = Context::new();
+        let org = parse(&mut ctx, content).unwrap_or_else(|e| panic!("{}", e));
+        let rems = org.get_reminders(&ReminderOptions::default());
+        assert_eq!(1, rems.len());
+        assert_eq!(chrono::Duration::minutes(5), rems[0].at - rems[0].datetime);
+        assert_eq!(vec!["work"], rems[0].tags);
+        assert_eq!(5, rems[0].line);
     }
 
     #[test]
@@ -0,0 +1,495 @@
+use crate::parser::{Org, Section};
+use crate::{Interval, Timestamp};
+use anyhow::{anyhow, bail, Result};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// Comparison in a term such as `priority:>=B`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    /// Split a leading operator off `s`, `=` when there is none.
+    fn split(s: &str) -> (Op, &str) {
+        for (prefix, op) in [
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+            ("=", Op::Eq),
+        ] {
+            if let Some(rest) = s.strip_prefix(prefix) {
+                return (op, rest);
+            }
+        }
+        (Op::Eq, s)
+    }
+
+    fn test(self, ord: Ordering) -> bool {
+        match self {
+            Op::Eq => ord == Ordering::Equal,
+            Op::Lt => ord == Ordering::Less,
+            Op::Le => ord != Ordering::Greater,
+            Op::Gt => ord == Ordering::Greater,
+            Op::Ge => ord != Ordering::Less,
+        }
+    }
+}
+
+/// A date in a term, relative ones resolved against today when evaluated.
+#[derive(Clone, Debug, PartialEq)]
+enum DateExpr {
+    Date(NaiveDate),
+    /// Today, shifted forward (or back when negative) by an interval.
+    Today(bool, Option<Interval>),
+}
+
+impl DateExpr {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "today" => DateExpr::Today(true, None),
+            "tomorrow" => DateExpr::Today(true, Some(Interval::parse("1d")?)),
+            "yesterday" => DateExpr::Today(false, Some(Interval::parse("1d")?)),
+            s => {
+                if let Some(rest) = s.strip_prefix('+') {
+                    DateExpr::Today(true, Some(Interval::parse(rest)?))
+                } else if let Some(rest) = s.strip_prefix('-') {
+                    DateExpr::Today(false, Some(Interval::parse(rest)?))
+                } else {
+                    DateExpr::Date(
+                        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                            .map_err(|_| anyhow!("invalid date: {}", s))?,
+                    )
+                }
+            }
+        })
+    }
+
+    fn resolve(&self, today: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            DateExpr::Date(date) => Some(*date),
+            DateExpr::Today(_, None) => Some(today),
+            DateExpr::Today(forward, Some(interval)) => {
+                let dt = today.and_time(NaiveTime::MIN);
+                let dt = if *forward {
+                    interval.shift(dt, 1)
+                } else {
+                    interval.before(dt)
+                };
+                dt.map(|dt| dt.date())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Predicate {
+    /// Open TODO keyword, or one of these whatever their state when any are given.
+    Todo(Vec<String>),
+    /// Done keyword, one of these when any are given.
+    Done(Vec<String>),
+    /// Any of these tags, inherited ones included.
+    Tags(Vec<String>),
+    /// Priority cookie, one of the list for `=`, otherwise compared to the only entry
+    /// with `A` as the highest.
+    Priority(Op, Vec<char>),
+    /// Scheduled at all, or on a date compared to the given one.
+    Scheduled(Option<(Op, DateExpr)>),
+    Deadline(Option<(Op, DateExpr)>),
+    Level(Op, usize),
+    /// Property set at all, or compared to a value.
+    Property(String, Option<(Op, String)>),
+    /// Case-insensitive substring of the title.
+    Heading(String),
+    /// Case-insensitive substring of the body.
+    Body(String),
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn parse_date_term(value: &str) -> Result<Option<(Op, DateExpr)>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let (op, value) = Op::split(value);
+    Ok(Some((op, DateExpr::parse(value)?)))
+}
+
+impl Predicate {
+    fn parse(key: &str, value: &str) -> Result<Option<Self>> {
+        Ok(Some(match key.to_ascii_lowercase().as_str() {
+            "todo" => Predicate::Todo(split_list(value)),
+            "done" => Predicate::Done(split_list(value)),
+            "tags" | "tag" => Predicate::Tags(split_list(value)),
+            "priority" => {
+                let (op, value) = Op::split(value);
+                let priorities = split_list(value)
+                    .iter()
+                    .map(|p| {
+                        let mut chars = p.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => Ok(c.to_ascii_uppercase()),
+                            _ => Err(anyhow!("invalid priority: {}", p)),
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if priorities.is_empty() || (op != Op::Eq && priorities.len() > 1) {
+                    bail!("invalid priority: {}", value);
+                }
+                Predicate::Priority(op, priorities)
+            }
+            "scheduled" => Predicate::Scheduled(parse_date_term(value)?),
+            "deadline" => Predicate::Deadline(parse_date_term(value)?),
+            "level" => {
+                let (op, value) = Op::split(value);
+                let level = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid level: {}", value))?;
+                Predicate::Level(op, level)
+            }
+            "property" | "prop" => {
+                let (key, cmp) = match value.find(['=', '<', '>']) {
+                    Some(pos) => {
+                        let (op, v) = Op::split(&value[pos..]);
+                        (&value[..pos], Some((op, v.to_string())))
+                    }
+                    None => (value, None),
+                };
+                if key.is_empty() {
+                    bail!("missing property name");
+                }
+                Predicate::Property(key.to_string(), cmp)
+            }
+            "heading" => Predicate::Heading(value.to_lowercase()),
+            "body" => Predicate::Body(value.to_lowercase()),
+            _ => return Ok(None),
+        }))
+    }
+
+    fn matches(&self, sec: &Section, tags: &[String], today: NaiveDate) -> bool {
+        match self {
+            Predicate::Todo(keywords) => sec.todo.as_ref().is_some_and(|todo| {
+                if keywords.is_empty() {
+                    !sec.done
+                } else {
+                    keywords.contains(todo)
+                }
+            }),
+            Predicate::Done(keywords) => {
+                sec.done
+                    && sec
+                        .todo
+                        .as_ref()
+                        .is_some_and(|todo| keywords.is_empty() || keywords.contains(todo))
+            }
+            Predicate::Tags(wanted) => wanted.iter().any(|t| tags.contains(t)),
+            Predicate::Priority(op, priorities) => sec.priority.is_some_and(|p| match op {
+                Op::Eq => priorities.contains(&p),
+                // `A` is the highest priority but the lowest char
+                _ => op.test(priorities[0].cmp(&p)),
+            }),
+            Predicate::Scheduled(cmp) => date_matches(sec.scheduled(), cmp, today),
+            Predicate::Deadline(cmp) => date_matches(sec.deadline(), cmp, today),
+            Predicate::Level(op, level) => op.test(sec.level.cmp(level)),
+            Predicate::Property(key, cmp) => match (sec.property(key), cmp) {
+                (Some(_), None) => true,
+                (Some(value), Some((op, expected))) => match compare_values(value, expected) {
+                    Some(ord) => op.test(ord),
+                    None => *op == Op::Eq && value.eq_ignore_ascii_case(expected),
+                },
+                (None, _) => false,
+            },
+            Predicate::Heading(text) => sec.title.to_lowercase().contains(text),
+            Predicate::Body(text) => sec
+                .contents
+                .iter()
+                .any(|c| c.contents.to_lowercase().contains(text)),
+        }
+    }
+}
+
+fn date_matches(ts: Option<&Timestamp>, cmp: &Option<(Op, DateExpr)>, today: NaiveDate) -> bool {
+    match (ts, cmp) {
+        (Some(_), None) => true,
+        (Some(ts), Some((op, expr))) => expr
+            .resolve(today)
+            .is_some_and(|date| op.test(ts.date.cmp(&date))),
+        (None, _) => false,
+    }
+}
+
+/// Minutes in an effort-like duration such as `1:30`, `90min`, `2h` or `1d`.
+fn parse_minutes(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if let Some((h, m)) = s.split_once(':') {
+        return Some(h.parse::<f64>().ok()? * 60.0 + m.parse::<f64>().ok()?);
+    }
+    let pos = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let value: f64 = s[..pos].parse().ok()?;
+    let unit = match s[pos..].trim() {
+        "min" | "m" => 1.0,
+        "h" => 60.0,
+        "d" => 60.0 * 24.0,
+        "w" => 60.0 * 24.0 * 7.0,
+        _ => return None,
+    };
+    Some(value * unit)
+}
+
+/// Order of two property values as durations, then as numbers, when both parse.
+fn compare_values(a: &str, b: &str) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (parse_minutes(a), parse_minutes(b)) {
+        return a.partial_cmp(&b);
+    }
+    if let (Ok(a), Ok(b)) = (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        return a.partial_cmp(&b);
+    }
+    None
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Term {
+    negated: bool,
+    predicate: Predicate,
+}
+
+/// Heading filter written like `todo:TODO tags:work scheduled:<today priority:A`.
+///
+/// Terms are separated by spaces and must all match; a leading `-` negates one.
+/// Words without a known `key:` prefix match the title, and double quotes keep
+/// spaces in a term.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+/// Split on whitespace outside double quotes, dropping the quotes.
+fn split_terms(s: &str) -> Result<Vec<String>> {
+    let mut terms = vec![];
+    let mut term = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            }
+            c => term.push(c),
+        }
+    }
+    if quoted {
+        bail!("unterminated quote");
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    Ok(terms)
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut terms = vec![];
+        for term in split_terms(s)? {
+            let (negated, term) = match term.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest),
+                _ => (false, term.as_str()),
+            };
+            let predicate = match term.split_once(':') {
+                Some((key, value)) => Predicate::parse(key, value)?,
+                None => None,
+            };
+            terms.push(Term {
+                negated,
+                predicate: predicate.unwrap_or_else(|| Predicate::Heading(term.to_lowercase())),
+            });
+        }
+        Ok(Query { terms })
+    }
+}
+
+impl Query {
+    /// Whether `sec` matches, given its tags including inherited ones.
+    pub fn matches(&self, sec: &Section, tags: &[String], today: NaiveDate) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term.predicate.matches(sec, tags, today) != term.negated)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QueryItem {
+    pub title: String,
+    pub todo: Option<String>,
+    pub done: bool,
+    pub priority: Option<char>,
+    pub tags: Vec<String>,
+    pub file: Option<String>,
+    pub line: usize,
+    pub level: usize,
+    pub id: Option<String>,
+    pub scheduled: Option<String>,
+    pub deadline: Option<String>,
+}
+
+/// Headings of `orgs` matching `query`, in document order.
+pub fn query<'a>(
+    orgs: impl IntoIterator<Item = &'a Org>,
+    query: &Query,
+    today: NaiveDate,
+) -> Vec<QueryItem> {
+    let mut items = vec![];
+    for org in orgs {
+        let tags = org.file_tags();
+        for sec in &org.sections {
+            collect(org, sec, &tags, query, today, &mut items);
+        }
+    }
+    items
+}
+
+fn collect(
+    org: &Org,
+    sec: &Section,
+    inherited: &[String],
+    query: &Query,
+    today: NaiveDate,
+    items: &mut Vec<QueryItem>,
+) {
+    let mut tags = inherited.to_vec();
+    for tag in &sec.tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+
+    if query.matches(sec, &tags, today) {
+        items.push(QueryItem {
+            title: sec.title.clone(),
+            todo: sec.todo.clone(),
+            done: sec.done,
+            priority: sec.priority,
+            tags: tags.clone(),
+            file: org.filename.clone(),
+            line: sec.line,
+            level: sec.level,
+            id: sec.property("ID").map(|id| id.to_string()),
+            scheduled: sec.scheduled().map(|ts| ts.to_string()),
+            deadline: sec.deadline().map(|ts| ts.to_string()),
+        });
+    }
+
+    for child in &sec.sections {
+        collect(org, child, &tags, query, today, items);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Context};
+
+    const CONTENT: &str = r#"#+FILETAGS: :home:
+* TODO [#A] Weekly review :work:
+SCHEDULED: <2024-03-01 Fri>
+:PROPERTIES:
+:EFFORT: 1:30
+:END:
+** WAIT Ask Bob about the budget
+DEADLINE: <2024-03-08 Fri>
+* DONE Call Alice
+SCHEDULED: <2024-03-04 Mon>
+* Notes
+:PROPERTIES:
+:EFFORT: 30min
+:END:
+Remember the milk.
+"#;
+
+    fn titles(q: &str) -> Vec<String> {
+        let mut ctx = Context::new();
+        ctx.todo_keywords = vec![crate::TodoKeywords::parse("TODO WAIT | DONE")];
+        let org = parse(&mut ctx, CONTENT).unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        let q: Query = q.parse().unwrap();
+        query([&org], &q, today)
+            .into_iter()
+            .map(|item| item.title)
+            .collect()
+    }
+
+    #[test]
+    fn test_query() {
+        assert_eq!(
+            vec!["Weekly review", "Ask Bob about the budget"],
+            titles("todo:")
+        );
+        assert_eq!(vec!["Ask Bob about the budget"], titles("todo:WAIT"));
+        assert_eq!(vec!["Call Alice"], titles("todo:DONE"));
+        assert_eq!(vec!["Call Alice"], titles("done:"));
+        assert_eq!(
+            vec!["Weekly review", "Ask Bob about the budget"],
+            titles("tags:work")
+        );
+        assert_eq!(4, titles("tags:home").len());
+        assert_eq!(vec!["Weekly review"], titles("priority:A"));
+        assert_eq!(vec!["Weekly review"], titles("priority:>=B"));
+        assert_eq!(vec!["Weekly review"], titles("todo: scheduled:<today"));
+        assert_eq!(
+            vec!["Weekly review", "Call Alice"],
+            titles("scheduled:<=2024-03-04")
+        );
+        assert_eq!(vec!["Ask Bob about the budget"], titles("deadline:<=+3d"));
+        assert_eq!(vec!["Call Alice"], titles("scheduled:yesterday"));
+        assert_eq!(vec!["Weekly review"], titles("property:EFFORT>1h"));
+        assert_eq!(vec!["Notes"], titles("property:effort<=30m"));
+        assert_eq!(vec!["Ask Bob about the budget"], titles("level:2"));
+        assert_eq!(vec!["Ask Bob about the budget"], titles("bob"));
+        assert_eq!(vec!["Ask Bob about the budget"], titles("\"about the\""));
+        assert_eq!(vec!["Notes"], titles("body:milk"));
+        assert_eq!(
+            vec!["Weekly review"],
+            titles("todo:TODO tags:work -level:2")
+        );
+        assert_eq!(vec!["Notes"], titles("-tags:work -scheduled:"));
+    }
+
+    #[test]
+    fn test_invalid_query() {
+        assert!("priority:AB".parse::<Query>().is_err());
+        assert!("priority:>A,B".parse::<Query>().is_err());
+        assert!("scheduled:<someday".parse::<Query>().is_err());
+        assert!("level:x".parse::<Query>().is_err());
+        assert!("property:=1".parse::<Query>().is_err());
+        assert!("heading:\"open".parse::<Query>().is_err());
+        // unknown keys are plain words
+        assert_eq!(
+            Ok(vec![Term {
+                negated: false,
+                predicate: Predicate::Heading("http://example.com".to_string())
+            }]),
+            "http://example.com"
+                .parse::<Query>()
+                .map(|q| q.terms)
+                .map_err(|e| e.to_string())
+        );
+    }
+}
@@ -1,18 +1,137 @@
 use crate::parser::{Scheduling, Section};
+use crate::{Interval, TimeUnit, WarningKind};
+use anyhow::{anyhow, bail, Result};
 use chrono::prelude::*;
+use chrono::Days;
 use std::hash::{Hash, Hasher};
 use std::time::Duration;
+use tracing::debug;
+
+/// Heading property and file keyword overriding the reminder offsets, e.g. `:REMINDERS: 2h 15m 0m`.
+pub const REMINDERS_KEY: &str = "REMINDERS";
+
+/// Settings that shape which reminders are generated for a document.
+#[derive(Clone, Debug)]
+pub struct ReminderOptions {
+    /// Lead time for deadlines without a warning cookie, like `org-deadline-warning-days`.
+    pub deadline_warning_days: u32,
+    /// How long before an item its reminders fire.
+    pub offsets: Vec<Duration>,
+    /// Skip headings in a done state such as `DONE`.
+    pub skip_done: bool,
+}
+
+impl Default for ReminderOptions {
+    fn default() -> Self {
+        ReminderOptions {
+            deadline_warning_days: 14,
+            offsets: vec![
+                Duration::from_secs(60 * 30),
+                Duration::from_secs(60 * 10),
+                Duration::from_secs(60),
+            ],
+            skip_done: true,
+        }
+    }
+}
+
+/// Parse a whitespace separated list of offsets such as `2h 15m 0m`.
+///
+/// Units are `s`, `m` (minutes), `h`, `d` and `w`.
+pub fn parse_offsets(s: &str) -> Result<Vec<Duration>> {
+    let mut offsets = vec![];
+    for token in s.split_whitespace() {
+        let Some(unit) = token.chars().last() else {
+            continue;
+        };
+        let value: u64 = token[..token.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| anyhow!("invalid reminder offset: {}", token))?;
+        let secs = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => bail!("invalid reminder offset: {}", token),
+        };
+        let secs = value
+            .checked_mul(secs)
+            .ok_or_else(|| anyhow!("invalid reminder offset: {}", token))?;
+        offsets.push(Duration::from_secs(secs));
+    }
+    Ok(offsets)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReminderKind {
+    /// Fires a fixed offset before a scheduled item or deadline.
+    Upcoming,
+    /// Daily notice during the warning period of a deadline.
+    DeadlineWarning,
+}
 
 #[derive(Clone, Debug, Eq)]
 pub struct Reminder {
+    /// Title of the heading.
     pub title: String,
+    /// File the heading comes from.
+    pub file: Option<String>,
+    /// `ID` property of the heading.
+    pub id: Option<String>,
+    /// Line of the heading in the file.
+    pub line: usize,
+    pub tags: Vec<String>,
+    /// Priority cookie of the heading, e.g. `A` for `[#A]`.
+    pub priority: Option<char>,
+    pub kind: ReminderKind,
+    /// When the notification fires.
     pub datetime: NaiveDateTime,
+    /// The occurrence of the scheduled item this reminder belongs to.
+    pub at: NaiveDateTime,
     pub scheduling: Scheduling,
+    /// Offsets in effect for the heading, reused for later occurrences.
+    pub offsets: Vec<Duration>,
+}
+
+impl Reminder {
+    /// How long before the occurrence this reminder fires.
+    pub fn offset(&self) -> Duration {
+        (self.at - self.datetime).to_std().unwrap_or_default()
+    }
+
+    /// Reminders for the occurrence following this one, if the timestamp has a repeater.
+    pub fn next_reminders(&self, opts: &ReminderOptions) -> Vec<Reminder> {
+        let ts = self.scheduling.timestamp();
+        if ts.repeater.is_none() {
+            return vec![];
+        }
+        match ts.next_occurrence(default_time(), self.at) {
+            Some(dt) => {
+                let mut reminders =
+                    create_reminder(&self.title, dt, &self.scheduling, opts, &self.offsets);
+                for r in &mut reminders {
+                    r.file.clone_from(&self.file);
+                    r.id.clone_from(&self.id);
+                    r.line = self.line;
+                    r.tags.clone_from(&self.tags);
+                    r.priority = self.priority;
+                }
+                reminders
+            }
+            None => vec![],
+        }
+    }
 }
 
 impl PartialEq for Reminder {
     fn eq(&self, other: &Self) -> bool {
-        self.title == other.title && self.datetime == other.datetime
+        self.title == other.title
+            && self.datetime == other.datetime
+            && self.kind == other.kind
+            && self.file == other.file
+            && self.id == other.id
+            && self.line == other.line
     }
 }
 
@@ -20,18 +139,47 @@ impl Hash for Reminder {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.title.hash(state);
         self.datetime.hash(state);
+        self.kind.hash(state);
+        self.file.hash(state);
+        self.id.hash(state);
+        self.line.hash(state);
     }
 }
 
-pub fn get_reminders(sec: &Section) -> Vec<Reminder> {
+/// Collect reminders of `sec` and its children, `offsets` being the ones inherited from the parent.
+pub fn get_reminders(
+    sec: &Section,
+    now: NaiveDateTime,
+    opts: &ReminderOptions,
+    offsets: &[Duration],
+) -> Vec<Reminder> {
+    let own = sec
+        .property(REMINDERS_KEY)
+        .and_then(|value| match parse_offsets(value) {
+            Ok(offsets) => Some(offsets),
+            Err(err) => {
+                debug!("ignore {}: {:?}", REMINDERS_KEY, err);
+                None
+            }
+        });
+    let offsets = own.as_deref().unwrap_or(offsets);
+
     let mut res = vec![];
-    for sch in &sec.scheduling {
-        if let Some(mut reminders) = convert_reminder(&sec.title, sch) {
-            res.append(&mut reminders);
+    if !(opts.skip_done && sec.done) {
+        for sch in &sec.scheduling {
+            if let Some(mut reminders) = convert_reminder(&sec.title, sch, now, opts, offsets) {
+                for r in &mut reminders {
+                    r.id = sec.property("ID").map(|id| id.to_string());
+                    r.line = sec.line;
+                    r.tags.clone_from(&sec.tags);
+                    r.priority = sec.priority;
+                }
+                res.append(&mut reminders);
+            }
         }
     }
     for sec in &sec.sections {
-        let mut reminders = get_reminders(sec);
+        let mut reminders = get_reminders(sec, now, opts, offsets);
         if !reminders.is_empty() {
             res.append(&mut reminders);
         }
@@ -39,82 +187,110 @@ pub fn get_reminders(sec: &Section) -> Vec<Reminder> {
     res
 }
 
-fn create_reminder(title: &str, dt: NaiveDateTime, sch: &Scheduling) -> Vec<Reminder> {
+fn create_reminder(
+    title: &str,
+    dt: NaiveDateTime,
+    sch: &Scheduling,
+    opts: &ReminderOptions,
+    offsets: &[Duration],
+) -> Vec<Reminder> {
     let mut vec = vec![];
+    if let Scheduling::Deadline(_) = sch {
+        vec.append(&mut create_warnings(title, dt, sch, opts, offsets));
+    }
+    for offset in offsets {
+        let Some(datetime) = chrono::Duration::from_std(*offset)
+            .ok()
+            .and_then(|d| dt.checked_sub_signed(d))
+        else {
+            debug!("skip reminder offset out of range: {} {:?}", title, offset);
+            continue;
+        };
+        let rem = Reminder {
+            title: title.to_string(),
+            file: None,
+            id: None,
+            line: 0,
+            tags: vec![],
+            priority: None,
+            kind: ReminderKind::Upcoming,
+            datetime,
+            at: dt,
+            scheduling: sch.clone(),
+            offsets: offsets.to_vec(),
+        };
+        vec.push(rem);
+    }
+    vec
+}
 
-    let rem = Reminder {
-        title: format!("このイベントまであと30分: {}", title),
-        datetime: dt - Duration::from_secs(60 * 30),
-        scheduling: sch.clone(),
+/// Daily reminders from the start of the deadline's warning period until the deadline.
+fn create_warnings(
+    title: &str,
+    dt: NaiveDateTime,
+    sch: &Scheduling,
+    opts: &ReminderOptions,
+    offsets: &[Duration],
+) -> Vec<Reminder> {
+    let ts = sch.timestamp();
+    let default_interval = Interval {
+        value: opts.deadline_warning_days,
+        unit: TimeUnit::Day,
     };
-    vec.push(rem);
-
-    let rem = Reminder {
-        title: format!("このイベントまであと10分: {}", title),
-        datetime: dt - Duration::from_secs(60 * 10),
-        scheduling: sch.clone(),
+    let interval = match ts.warning {
+        // `--3d` only applies to the first occurrence of a repeated deadline
+        Some(w) if w.kind == WarningKind::First && dt != ts.datetime_or(default_time()) => {
+            default_interval
+        }
+        Some(w) => w.interval,
+        None => default_interval,
     };
-    vec.push(rem);
 
-    let rem = Reminder {
-        title: format!("このイベントまであと1分: {}", title),
-        datetime: dt - Duration::from_secs(60),
-        scheduling: sch.clone(),
+    let mut vec = vec![];
+    let Some(mut datetime) = interval.before(dt) else {
+        return vec;
     };
-    vec.push(rem);
+    while datetime < dt {
+        let rem = Reminder {
+            title: title.to_string(),
+            file: None,
+            id: None,
+            line: 0,
+            tags: vec![],
+            priority: None,
+            kind: ReminderKind::DeadlineWarning,
+            datetime,
+            at: dt,
+            scheduling: sch.clone(),
+            offsets: offsets.to_vec(),
+        };
+        vec.push(rem);
+        let Some(next) = datetime.checked_add_days(Days::new(1)) else {
+            break;
+        };
+        datetime = next;
+    }
     vec
 }
 
-// TODO refactor
-fn convert_reminder(title: &str, sch: &Scheduling) -> Option<Vec<Reminder>> {
-    let now = Local::now().naive_local();
-    match sch {
-        Scheduling::Scheduled(ref datetime) => {
-            let dt = NaiveDateTime::parse_from_str(datetime, "%F %a %R");
-
-            if let Ok(dt) = dt {
-                if dt > now {
-                    Some(create_reminder(title, dt, sch))
-                } else {
-                    None
-                }
-            } else {
-                let datetime = format!("{} 09:00", &datetime);
-                let dt = NaiveDateTime::parse_from_str(&datetime, "%F %a %R");
-                if let Ok(dt) = dt {
-                    if dt > now {
-                        Some(create_reminder(title, dt, sch))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
-        }
-        Scheduling::Deadline(ref datetime) => {
-            let dt = NaiveDateTime::parse_from_str(datetime, "%F %a %R");
-            if let Ok(dt) = dt {
-                if dt > now {
-                    Some(create_reminder(title, dt, sch))
-                } else {
-                    None
-                }
-            } else {
-                let datetime = format!("{} 09:00", &datetime);
-                let dt = NaiveDateTime::parse_from_str(&datetime, "%F %a %R");
-                if let Ok(dt) = dt {
-                    if dt > now {
-                        Some(create_reminder(title, dt, sch))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
-        }
+/// Time used for timestamps that only carry a date.
+fn default_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap_or_default()
+}
+
+fn convert_reminder(
+    title: &str,
+    sch: &Scheduling,
+    now: NaiveDateTime,
+    opts: &ReminderOptions,
+    offsets: &[Duration],
+) -> Option<Vec<Reminder>> {
+    let ts = sch.timestamp();
+    if !ts.active {
+        return None;
     }
+    let dt = ts.next_occurrence(default_time(), now)?;
+    Some(create_reminder(title, dt, sch, opts, offsets))
 }
 
 #[cfg(test)]
@@ -126,20 +302,161 @@ mod tests {
         let _ = tracing_subscriber::fmt::try_init();
     }
 
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%F %R").unwrap()
+    }
+
     #[test]
     fn test_convert_reminder() {
         // SCHEDULED: <2024-03-04 Mon 10:00>
         init();
+        let now = at("2026-10-17 12:00");
+        let opts = ReminderOptions::default();
+        let ts = "<2024-03-04 Mon 13:00>".parse().unwrap();
         let rem = convert_reminder(
             "title",
-            &Scheduling::Scheduled("2024-03-04 Mon 13:00".to_string()),
+            &Scheduling::Scheduled(ts),
+            now,
+            &opts,
+            &opts.offsets,
         );
         debug!("{:?}", rem);
+        assert!(rem.is_none());
 
+        let ts = "<2099-03-04 Wed>".parse().unwrap();
         let rem = convert_reminder(
             "title",
-            &Scheduling::Scheduled("2024-03-04 Mon".to_string()),
-        );
+            &Scheduling::Scheduled(ts),
+            now,
+            &opts,
+            &opts.offsets,
+        )
+        .unwrap();
         debug!("{:?}", rem);
+        assert_eq!(3, rem.len());
+        assert_eq!(
+            NaiveDate::from_ymd_opt(2099, 3, 4)
+                .unwrap()
+                .and_hms_opt(8, 30, 0)
+                .unwrap(),
+            rem[0].datetime
+        );
+    }
+
+    #[test]
+    fn test_repeated_reminder() {
+        init();
+        // SCHEDULED: <2024-03-04 Mon 10:00 +1w>
+        let now = at("2026-10-17 12:00");
+        let opts = ReminderOptions::default();
+        let ts = "<2024-03-04 Mon 10:00 +1w>".parse().unwrap();
+        let rem = convert_reminder(
+            "standup",
+            &Scheduling::Scheduled(ts),
+            now,
+            &opts,
+            &opts.offsets,
+        )
+        .unwrap();
+        assert_eq!(3, rem.len());
+        assert_eq!(at("2026-10-19 10:00"), rem[0].at);
+        assert_eq!(at("2026-10-19 09:30"), rem[0].datetime);
+        assert_eq!("standup", rem[0].title);
+        assert_eq!(Duration::from_secs(30 * 60), rem[0].offset());
+
+        let next = rem[2].next_reminders(&opts);
+        assert_eq!(3, next.len());
+        assert_eq!(at("2026-10-26 10:00"), next[0].at);
+        assert_eq!(at("2026-10-26 09:59"), next[2].datetime);
+
+        let ts = "<2024-03-04 Mon 10:00>".parse().unwrap();
+        let rem = Reminder {
+            title: "title".to_string(),
+            file: None,
+            id: None,
+            line: 0,
+            tags: vec![],
+            priority: None,
+            kind: ReminderKind::Upcoming,
+            datetime: at("2024-03-04 09:30"),
+            at: at("2024-03-04 10:00"),
+            scheduling: Scheduling::Scheduled(ts),
+            offsets: opts.offsets.clone(),
+        };
+        assert!(rem.next_reminders(&opts).is_empty());
+    }
+
+    #[test]
+    fn test_deadline_warning() {
+        init();
+        let now = at("2026-10-17 12:00");
+        let opts = ReminderOptions {
+            deadline_warning_days: 2,
+            ..Default::default()
+        };
+
+        // DEADLINE: <2099-03-10 Tue -3d>
+        let ts = "<2099-03-10 Tue -3d>".parse().unwrap();
+        let rem = convert_reminder(
+            "report",
+            &Scheduling::Deadline(ts),
+            now,
+            &opts,
+            &opts.offsets,
+        )
+        .unwrap();
+        let warnings: Vec<_> = rem
+            .iter()
+            .filter(|r| r.kind == ReminderKind::DeadlineWarning)
+            .collect();
+        assert_eq!(3, warnings.len());
+        assert_eq!(at("2099-03-07 09:00"), warnings[0].datetime);
+        assert_eq!(at("2099-03-09 09:00"), warnings[2].datetime);
+        assert_eq!(6, rem.len());
+
+        // falls back to deadline_warning_days
+        let ts = "<2099-03-10 Tue 17:00>".parse().unwrap();
+        let rem = convert_reminder(
+            "report",
+            &Scheduling::Deadline(ts),
+            now,
+            &opts,
+            &opts.offsets,
+        )
+        .unwrap();
+        let warnings: Vec<_> = rem
+            .iter()
+            .filter(|r| r.kind == ReminderKind::DeadlineWarning)
+            .collect();
+        assert_eq!(2, warnings.len());
+        assert_eq!(at("2099-03-08 17:00"), warnings[0].datetime);
+
+        // scheduled items get no warnings
+        let ts = "<2099-03-10 Tue -3d>".parse().unwrap();
+        let rem = convert_reminder(
+            "report",
+            &Scheduling::Scheduled(ts),
+            now,
+            &opts,
+            &opts.offsets,
+        )
+        .unwrap();
+        assert!(rem.iter().all(|r| r.kind == ReminderKind::Upcoming));
+    }
+
+    #[test]
+    fn test_parse_offsets() {
+        let offsets = parse_offsets("2h 15m  0m 1d").unwrap();
+        assert_eq!(
+            vec![
+                Duration::from_secs(2 * 60 * 60),
+                Duration::from_secs(15 * 60),
+                Duration::ZERO,
+                Duration::from_secs(24 * 60 * 60),
+            ],
+            offsets
+        );
+        assert!(parse_offsets("2x").is_err());
+        assert!(parse_offsets("h").is_err());
     }
 }
@@ -0,0 +1,500 @@
+use crate::parser::{OrgParser, Rule, Span};
+use anyhow::{anyhow, bail, Result};
+use chrono::prelude::*;
+use chrono::{Days, Duration, Months};
+use pest::iterators::Pair;
+use pest::Parser;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeUnit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl TimeUnit {
+    fn as_char(&self) -> char {
+        match self {
+            TimeUnit::Hour => 'h',
+            TimeUnit::Day => 'd',
+            TimeUnit::Week => 'w',
+            TimeUnit::Month => 'm',
+            TimeUnit::Year => 'y',
+        }
+    }
+}
+
+/// An amount of time as written in repeater and warning cookies, e.g. `1w` or `3d`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Interval {
+    pub value: u32,
+    pub unit: TimeUnit,
+}
+
+impl Interval {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let Some(unit) = s.chars().last() else {
+            bail!("empty interval");
+        };
+        let unit = match unit {
+            'h' => TimeUnit::Hour,
+            'd' => TimeUnit::Day,
+            'w' => TimeUnit::Week,
+            'm' => TimeUnit::Month,
+            'y' => TimeUnit::Year,
+            _ => bail!("unknown time unit: {}", s),
+        };
+        let value = s[..s.len() - 1].parse()?;
+        Ok(Interval { value, unit })
+    }
+
+    /// Shift `dt` forward by `n` times this interval.
+    pub fn shift(&self, dt: NaiveDateTime, n: u32) -> Option<NaiveDateTime> {
+        let n = self.value.checked_mul(n)?;
+        match self.unit {
+            TimeUnit::Hour => dt.checked_add_signed(Duration::try_hours(n.into())?),
+            TimeUnit::Day => dt.checked_add_days(Days::new(n.into())),
+            TimeUnit::Week => dt.checked_add_days(Days::new(u64::from(n) * 7)),
+            TimeUnit::Month => dt.checked_add_months(Months::new(n)),
+            TimeUnit::Year => dt.checked_add_months(Months::new(n.checked_mul(12)?)),
+        }
+    }
+
+    /// Move `dt` back by this interval.
+    pub fn before(&self, dt: NaiveDateTime) -> Option<NaiveDateTime> {
+        let n = self.value;
+        match self.unit {
+            TimeUnit::Hour => dt.checked_sub_signed(Duration::try_hours(n.into())?),
+            TimeUnit::Day => dt.checked_sub_days(Days::new(n.into())),
+            TimeUnit::Week => dt.checked_sub_days(Days::new(u64::from(n) * 7)),
+            TimeUnit::Month => dt.checked_sub_months(Months::new(n)),
+            TimeUnit::Year => dt.checked_sub_months(Months::new(n.checked_mul(12)?)),
+        }
+    }
+
+    /// Number of whole intervals between `from` and `to`, possibly underestimated.
+    fn count_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> u32 {
+        if self.value == 0 || to <= from {
+            return 0;
+        }
+        let n = match self.unit {
+            TimeUnit::Hour => (to - from).num_hours(),
+            TimeUnit::Day => (to - from).num_days(),
+            TimeUnit::Week => (to - from).num_weeks(),
+            TimeUnit::Month => {
+                i64::from(to.year() - from.year()) * 12 + i64::from(to.month())
+                    - i64::from(from.month())
+                    - 1
+            }
+            TimeUnit::Year => i64::from(to.year() - from.year()) - 1,
+        };
+        u32::try_from(n.max(0) / i64::from(self.value)).unwrap_or(u32::MAX)
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.value, self.unit.as_char())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RepeaterKind {
+    /// `+1w`
+    Cumulate,
+    /// `++1w`
+    CatchUp,
+    /// `.+1w`
+    Restart,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Repeater {
+    pub kind: RepeaterKind,
+    pub interval: Interval,
+}
+
+impl fmt::Display for Repeater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = match self.kind {
+            RepeaterKind::Cumulate => "+",
+            RepeaterKind::CatchUp => "++",
+            RepeaterKind::Restart => ".+",
+        };
+        write!(f, "{}{}", mark, self.interval)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// `-3d`
+    All,
+    /// `--3d`
+    First,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub interval: Interval,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = match self.kind {
+            WarningKind::All => "-",
+            WarningKind::First => "--",
+        };
+        write!(f, "{}{}", mark, self.interval)
+    }
+}
+
+/// A parsed org timestamp such as `<2024-03-04 Mon 10:00-11:00 +1w -3d>`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timestamp {
+    pub active: bool,
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+    pub end_time: Option<NaiveTime>,
+    pub repeater: Option<Repeater>,
+    pub warning: Option<Warning>,
+    /// Where the timestamp was parsed from, brackets included. Not part of its value,
+    /// so equality and hashing ignore it.
+    #[serde(default)]
+    pub span: Span,
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.active == other.active
+            && self.date == other.date
+            && self.time == other.time
+            && self.end_time == other.end_time
+            && self.repeater == other.repeater
+            && self.warning == other.warning
+    }
+}
+
+impl Eq for Timestamp {}
+
+impl Hash for Timestamp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.active.hash(state);
+        self.date.hash(state);
+        self.time.hash(state);
+        self.end_time.hash(state);
+        self.repeater.hash(state);
+        self.warning.hash(state);
+    }
+}
+
+impl Timestamp {
+    /// Build from an `active_timestamp`, `inactive_timestamp` or `timestamp` pair.
+    pub(crate) fn from_pair(pair: Pair<'_, Rule>) -> Result<Self> {
+        let pair = match pair.as_rule() {
+            Rule::timestamp => pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| anyhow!("empty timestamp"))?,
+            _ => pair,
+        };
+        let span = pair.as_span().into();
+        let active = match pair.as_rule() {
+            Rule::active_timestamp => true,
+            Rule::inactive_timestamp => false,
+            rule => bail!("unexpected rule {:?}", rule),
+        };
+
+        let mut date = None;
+        let mut time = None;
+        let mut end_time = None;
+        let mut repeater = None;
+        let mut warning = None;
+
+        for pair in pair.into_inner() {
+            let s = pair.as_str();
+            match pair.as_rule() {
+                Rule::ts_date => {
+                    date = Some(NaiveDate::parse_from_str(s, "%F")?);
+                }
+                Rule::ts_time => {
+                    time = Some(NaiveTime::parse_from_str(s, "%R")?);
+                }
+                Rule::ts_end_time => {
+                    end_time = Some(NaiveTime::parse_from_str(s, "%R")?);
+                }
+                Rule::ts_repeater => {
+                    let (kind, rest) = if let Some(rest) = s.strip_prefix("++") {
+                        (RepeaterKind::CatchUp, rest)
+                    } else if let Some(rest) = s.strip_prefix(".+") {
+                        (RepeaterKind::Restart, rest)
+                    } else {
+                        (RepeaterKind::Cumulate, &s[1..])
+                    };
+                    repeater = Some(Repeater {
+                        kind,
+                        interval: Interval::parse(rest)?,
+                    });
+                }
+                Rule::ts_warning => {
+                    let (kind, rest) = if let Some(rest) = s.strip_prefix("--") {
+                        (WarningKind::First, rest)
+                    } else {
+                        (WarningKind::All, &s[1..])
+                    };
+                    warning = Some(Warning {
+                        kind,
+                        interval: Interval::parse(rest)?,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Timestamp {
+            active,
+            date: date.ok_or_else(|| anyhow!("timestamp without date"))?,
+            time,
+            end_time,
+            repeater,
+            warning,
+            span,
+        })
+    }
+
+    /// Start of the timestamp, using `default_time` when no time of day is given.
+    pub fn datetime_or(&self, default_time: NaiveTime) -> NaiveDateTime {
+        self.date.and_time(self.time.unwrap_or(default_time))
+    }
+
+    pub fn has_time(&self) -> bool {
+        self.time.is_some()
+    }
+
+    /// First occurrence strictly after `after`, following the repeater if there is one.
+    pub fn next_occurrence(
+        &self,
+        default_time: NaiveTime,
+        after: NaiveDateTime,
+    ) -> Option<NaiveDateTime> {
+        let start = self.datetime_or(default_time);
+        if start > after {
+            return Some(start);
+        }
+        let interval = self.repeater?.interval;
+        if interval.value == 0 {
+            return None;
+        }
+        let mut n = interval.count_between(start, after);
+        loop {
+            let dt = interval.shift(start, n)?;
+            if dt > after {
+                return Some(dt);
+            }
+            n = n.checked_add(1)?;
+        }
+    }
+
+    /// The timestamp moved to its next repetition, as org-mode does when a repeated
+    /// item is marked done at `now`. `None` without a repeater.
+    pub fn repeat(&self, now: NaiveDateTime) -> Option<Timestamp> {
+        let repeater = self.repeater?;
+        let interval = repeater.interval;
+        if interval.value == 0 {
+            return None;
+        }
+        // the time of day only matters for hourly repeaters
+        let start = self.datetime_or(now.time());
+        let next = match repeater.kind {
+            RepeaterKind::Cumulate => interval.shift(start, 1)?,
+            RepeaterKind::CatchUp => {
+                let mut n = interval.count_between(start, now).max(1);
+                loop {
+                    let dt = interval.shift(start, n)?;
+                    if dt > now {
+                        break dt;
+                    }
+                    n = n.checked_add(1)?;
+                }
+            }
+            RepeaterKind::Restart => match interval.unit {
+                TimeUnit::Hour => interval.shift(now, 1)?,
+                _ => interval.shift(now.date().and_time(start.time()), 1)?,
+            },
+        };
+        let mut ts = self.clone();
+        ts.date = next.date();
+        if interval.unit == TimeUnit::Hour && ts.time.is_some() {
+            ts.time = Some(next.time());
+        }
+        Some(ts)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let pair = OrgParser::parse(Rule::timestamp, s)?
+            .next()
+            .ok_or_else(|| anyhow!("invalid timestamp: {}", s))?;
+        if pair.as_str().len() != s.len() {
+            bail!("invalid timestamp: {}", s);
+        }
+        Timestamp::from_pair(pair)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (open, close) = if self.active { ('<', '>') } else { ('[', ']') };
+        write!(f, "{}{}", open, self.date.format("%F %a"))?;
+        if let Some(time) = self.time {
+            write!(f, " {}", time.format("%R"))?;
+            if let Some(end_time) = self.end_time {
+                write!(f, "-{}", end_time.format("%R"))?;
+            }
+        }
+        if let Some(repeater) = self.repeater {
+            write!(f, " {}", repeater)?;
+        }
+        if let Some(warning) = self.warning {
+            write!(f, " {}", warning)?;
+        }
+        write!(f, "{}", close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() -> Result<()> {
+        let ts: Timestamp = "<2024-03-04 Mon 10:00-11:30 +1w -3d>".parse()?;
+        assert!(ts.active);
+        assert_eq!(NaiveDate::from_ymd_opt(2024, 3, 4).unwrap(), ts.date);
+        assert_eq!(NaiveTime::from_hms_opt(10, 0, 0), ts.time);
+        assert_eq!(NaiveTime::from_hms_opt(11, 30, 0), ts.end_time);
+        assert_eq!(
+            Some(Repeater {
+                kind: RepeaterKind::Cumulate,
+                interval: Interval {
+                    value: 1,
+                    unit: TimeUnit::Week
+                }
+            }),
+            ts.repeater
+        );
+        assert_eq!(
+            Some(Warning {
+                kind: WarningKind::All,
+                interval: Interval {
+                    value: 3,
+                    unit: TimeUnit::Day
+                }
+            }),
+            ts.warning
+        );
+        assert_eq!("<2024-03-04 Mon 10:00-11:30 +1w -3d>", ts.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timestamp_date_only() -> Result<()> {
+        let ts: Timestamp = "[2024-03-04 月]".parse()?;
+        assert!(!ts.active);
+        assert_eq!(None, ts.time);
+        assert_eq!(None, ts.repeater);
+
+        let ts: Timestamp = "<2024-03-04 Mon .+2d --1w>".parse()?;
+        assert_eq!(RepeaterKind::Restart, ts.repeater.unwrap().kind);
+        assert_eq!(WarningKind::First, ts.warning.unwrap().kind);
+
+        let ts: Timestamp = "<2024-03-04 ++1m>".parse()?;
+        assert_eq!(RepeaterKind::CatchUp, ts.repeater.unwrap().kind);
+        assert_eq!(TimeUnit::Month, ts.repeater.unwrap().interval.unit);
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_occurrence() -> Result<()> {
+        let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%F %R").unwrap();
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00 +1w>".parse()?;
+        assert_eq!(
+            Some(at("2024-03-04 10:00")),
+            ts.next_occurrence(nine, at("2024-03-01 00:00"))
+        );
+        assert_eq!(
+            Some(at("2024-03-11 10:00")),
+            ts.next_occurrence(nine, at("2024-03-04 10:00"))
+        );
+        assert_eq!(
+            Some(at("2026-10-19 10:00")),
+            ts.next_occurrence(nine, at("2026-10-17 12:00"))
+        );
+
+        let ts: Timestamp = "<2024-01-31 Wed .+1m>".parse()?;
+        assert_eq!(
+            Some(at("2024-04-30 09:00")),
+            ts.next_occurrence(nine, at("2024-04-01 00:00"))
+        );
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00 ++2h>".parse()?;
+        assert_eq!(
+            Some(at("2024-03-05 12:00")),
+            ts.next_occurrence(nine, at("2024-03-05 11:00"))
+        );
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00>".parse()?;
+        assert_eq!(None, ts.next_occurrence(nine, at("2024-03-05 00:00")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat() -> Result<()> {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%F %R").unwrap();
+        let now = at("2024-03-20 12:00");
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00 +1w>".parse()?;
+        assert_eq!(
+            "<2024-03-11 Mon 10:00 +1w>",
+            ts.repeat(now).unwrap().to_string()
+        );
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00 ++1w -1d>".parse()?;
+        assert_eq!(
+            "<2024-03-25 Mon 10:00 ++1w -1d>",
+            ts.repeat(now).unwrap().to_string()
+        );
+
+        let ts: Timestamp = "<2024-03-04 Mon .+2d>".parse()?;
+        assert_eq!("<2024-03-22 Fri .+2d>", ts.repeat(now).unwrap().to_string());
+
+        let ts: Timestamp = "<2024-03-20 Wed 11:00 ++2h>".parse()?;
+        assert_eq!(
+            "<2024-03-20 Wed 13:00 ++2h>",
+            ts.repeat(now).unwrap().to_string()
+        );
+
+        let ts: Timestamp = "<2024-03-04 Mon 10:00>".parse()?;
+        assert_eq!(None, ts.repeat(now));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timestamp_invalid() {
+        assert!("<2024-03-04 Mon".parse::<Timestamp>().is_err());
+        assert!("2024-03-04".parse::<Timestamp>().is_err());
+        assert!("<2024-13-04 Mon>".parse::<Timestamp>().is_err());
+    }
+}
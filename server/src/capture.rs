@@ -0,0 +1,290 @@
+use crate::edit::modify_file;
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use org_parser::{Context, Section, Span, Timestamp};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Where `POST /api/capture` puts new entries, one `[[capture]]` table of the config.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaptureTemplate {
+    pub name: String,
+    /// Target file, relative to the first `org_path` unless absolute.
+    pub file: String,
+    /// Titles of the heading entries go under, separated by `/`, e.g. `Projects/Garden`.
+    /// Missing headings are created; entries go at the end of the file when unset.
+    pub heading: Option<String>,
+    /// TODO keyword of new entries.
+    pub todo: Option<String>,
+    /// Tags added to every entry.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CaptureRequest {
+    /// Name of the template to use.
+    pub template: String,
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// `2024-03-05`, `2024-03-05 10:00` or an org timestamp.
+    pub scheduled: Option<String>,
+}
+
+/// A checked entry, ready to be written under a heading of any level.
+#[derive(Debug)]
+pub struct Entry {
+    todo: Option<String>,
+    title: String,
+    tags: Vec<String>,
+    scheduled: Option<Timestamp>,
+    body: String,
+}
+
+fn parse_scheduled(s: &str) -> Result<Timestamp> {
+    let s = s.trim();
+    if s.starts_with('<') {
+        return s.parse();
+    }
+    let (date, time) = match NaiveDateTime::parse_from_str(s, "%F %R") {
+        Ok(dt) => (dt.date(), Some(dt.time())),
+        Err(_) => match NaiveDate::parse_from_str(s, "%F") {
+            Ok(date) => (date, None),
+            Err(_) => bail!("invalid scheduled date: {}", s),
+        },
+    };
+    Ok(Timestamp {
+        active: true,
+        date,
+        time,
+        end_time: None,
+        repeater: None,
+        warning: None,
+        span: Span::default(),
+    })
+}
+
+impl CaptureRequest {
+    /// The entry to write with `template`, rejecting input that would not stay a
+    /// single well-formed heading.
+    pub fn entry(&self, template: &CaptureTemplate) -> Result<Entry> {
+        let title = self.title.trim();
+        if title.is_empty() || title.contains('\n') {
+            bail!("title must be a single non-empty line");
+        }
+        let mut tags: Vec<String> = vec![];
+        for tag in template.tags.iter().chain(self.tags.iter()) {
+            let tag = tag.trim();
+            if tag.is_empty() || tag.contains(|c: char| c == ':' || c.is_whitespace()) {
+                bail!("invalid tag: {:?}", tag);
+            }
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        let headline = Regex::new(r"^\*+\s")?;
+        if self.body.lines().any(|line| headline.is_match(line)) {
+            bail!("body must not contain headings");
+        }
+        let scheduled = self.scheduled.as_deref().map(parse_scheduled).transpose()?;
+        Ok(Entry {
+            todo: template.todo.clone(),
+            title: title.to_string(),
+            tags,
+            scheduled,
+            body: self.body.trim_end().to_string(),
+        })
+    }
+}
+
+impl Entry {
+    fn render(&self, level: usize) -> String {
+        let mut text = "*".repeat(level);
+        if let Some(todo) = &self.todo {
+            text.push(' ');
+            text.push_str(todo);
+        }
+        text.push(' ');
+        text.push_str(&self.title);
+        if !self.tags.is_empty() {
+            text.push_str(&format!(" :{}:", self.tags.join(":")));
+        }
+        text.push('\n');
+        if let Some(ts) = &self.scheduled {
+            text.push_str(&format!("SCHEDULED: {}\n", ts));
+        }
+        if !self.body.is_empty() {
+            text.push_str(&self.body);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// `content` with `entry` appended under `heading`, and the line the entry starts on.
+pub fn capture_content(
+    content: &str,
+    heading: Option<&str>,
+    entry: &Entry,
+    ctx: &Context,
+) -> Result<(String, usize)> {
+    let mut ctx = ctx.clone();
+    let org = org_parser::parse(&mut ctx, content)?;
+    let mut res = content.to_string();
+    if !res.is_empty() && !res.ends_with('\n') {
+        res.push('\n');
+    }
+
+    // deepest existing heading of the path, the rest is created
+    let titles: Vec<&str> = heading
+        .map(|h| h.split('/').map(|t| t.trim()).collect())
+        .unwrap_or_default();
+    let mut parent: Option<&Section> = None;
+    let mut sections = &org.sections;
+    let mut missing: &[&str] = &[];
+    for (i, title) in titles.iter().enumerate() {
+        match sections.iter().find(|sec| sec.title == *title) {
+            Some(sec) => {
+                parent = Some(sec);
+                sections = &sec.sections;
+            }
+            None => {
+                missing = &titles[i..];
+                break;
+            }
+        }
+    }
+
+    let mut level = parent.map(|sec| sec.level + 1).unwrap_or(1);
+    // subtrees ending the file go after the newline added above
+    let at = match parent {
+        Some(sec) if sec.span.end < content.len() => sec.span.end,
+        _ => res.len(),
+    };
+    let mut new = String::new();
+    for title in missing {
+        new.push_str(&format!("{} {}\n", "*".repeat(level), title));
+        level += 1;
+    }
+    let line = res[..at].matches('\n').count() + missing.len() + 1;
+    new.push_str(&entry.render(level));
+    res.insert_str(at, &new);
+    Ok((res, line))
+}
+
+/// Write `entry` into `path`, creating the file if needed. Returns the line of the entry.
+pub async fn capture(
+    path: &Path,
+    heading: Option<&str>,
+    entry: &Entry,
+    ctx: &Context,
+) -> Result<usize> {
+    modify_file(path, true, |content| {
+        capture_content(content, heading, entry, ctx)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(heading: Option<&str>) -> CaptureTemplate {
+        CaptureTemplate {
+            name: "todo".to_string(),
+            file: "inbox.org".to_string(),
+            heading: heading.map(|h| h.to_string()),
+            todo: Some("TODO".to_string()),
+            tags: vec!["inbox".to_string()],
+        }
+    }
+
+    fn request(title: &str) -> CaptureRequest {
+        CaptureRequest {
+            template: "todo".to_string(),
+            title: title.to_string(),
+            body: "Bring the receipts.\n".to_string(),
+            tags: vec!["work".to_string(), "inbox".to_string()],
+            scheduled: Some("2024-03-05 10:00".to_string()),
+        }
+    }
+
+    const CONTENT: &str = "#+TITLE: Inbox\n* Tasks\n** TODO Old task\nSome text\n* Notes\n";
+
+    #[test]
+    fn test_capture() {
+        let ctx = Context::new();
+        let entry = request("Call Bob").entry(&template(Some("Tasks"))).unwrap();
+        let (res, line) = capture_content(CONTENT, Some("Tasks"), &entry, &ctx).unwrap();
+        assert_eq!(
+            "#+TITLE: Inbox\n* Tasks\n** TODO Old task\nSome text\n** TODO Call Bob :inbox:work:\nSCHEDULED: <2024-03-05 Tue 10:00>\nBring the receipts.\n* Notes\n",
+            res
+        );
+        assert_eq!(5, line);
+
+        // the end of the file, without a trailing newline
+        let entry = request("Call Bob").entry(&template(None)).unwrap();
+        let (res, line) = capture_content("* Notes", None, &entry, &ctx).unwrap();
+        assert!(res.starts_with("* Notes\n* TODO Call Bob :inbox:work:\n"));
+        assert_eq!(2, line);
+
+        // missing headings are created
+        let (res, line) = capture_content(CONTENT, Some("Notes/Calls"), &entry, &ctx).unwrap();
+        assert!(res.ends_with("* Notes\n** Calls\n*** TODO Call Bob :inbox:work:\nSCHEDULED: <2024-03-05 Tue 10:00>\nBring the receipts.\n"));
+        assert_eq!(7, line);
+        let (res, _) = capture_content("", Some("Inbox"), &entry, &ctx).unwrap();
+        assert!(res.starts_with("* Inbox\n** TODO Call Bob"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_symlink() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = crate::fixtures::temp_dir("capture-symlink");
+        let target = dir.join("sync").join("inbox.org");
+        std::fs::create_dir(target.parent().unwrap()).unwrap();
+        std::fs::write(&target, "* Tasks\n").unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.join("inbox.org");
+        symlink(&target, &link).unwrap();
+
+        let ctx = Context::new();
+        let entry = request("Call Bob").entry(&template(None)).unwrap();
+        capture(&link, Some("Tasks"), &entry, &ctx).await.unwrap();
+
+        // the link still points at the file, which got the entry and kept its mode
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let content = std::fs::read_to_string(&target).unwrap();
+        assert!(content.starts_with("* Tasks\n** TODO Call Bob :inbox:work:\n"));
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        assert!(!target.with_extension("org.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_entry() {
+        let template = template(None);
+        assert!(request(" ").entry(&template).is_err());
+        assert!(request("two\nlines").entry(&template).is_err());
+        let mut req = request("Call Bob");
+        req.tags = vec!["two words".to_string()];
+        assert!(req.entry(&template).is_err());
+        let mut req = request("Call Bob");
+        req.body = "text\n* heading".to_string();
+        assert!(req.entry(&template).is_err());
+        let mut req = request("Call Bob");
+        req.scheduled = Some("tomorrow".to_string());
+        assert!(req.entry(&template).is_err());
+        req.scheduled = Some("<2024-03-05 Tue +1w>".to_string());
+        assert!(req.entry(&template).is_ok());
+    }
+}
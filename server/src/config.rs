@@ -1,12 +1,122 @@
+use crate::{
+    capture::CaptureTemplate,
+    message::MessageConfig,
+    notification::{NotifierConfig, Notifiers},
+    parse::FileFilter,
+    reminders::OrgEvent,
+    utils,
+};
 use anyhow::Result;
+use org_parser::{parse_offsets, Context, ReminderOptions, TodoKeywords};
 use serde::Deserialize;
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc::Sender;
 use tracing::info;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub org_path: Vec<String>,
+    /// Globs selecting the files to index under each `org_path`.
+    #[serde(default = "default_include")]
+    pub include: Vec<String>,
+    /// Globs of files to skip, e.g. `["archive/**", "*.org_archive", ".git/**"]`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
     pub server_port: u32,
+    #[serde(default = "default_deadline_warning_days")]
+    pub deadline_warning_days: u32,
+    /// Default reminder offsets, e.g. `["30m", "10m", "1m"]`.
+    pub reminder_offsets: Option<Vec<String>>,
+    /// Don't remind of headings in a done state.
+    #[serde(default = "default_skip_done")]
+    pub skip_done: bool,
+    #[serde(default)]
+    pub messages: MessageConfig,
+    /// Where reminders are delivered; every entry receives each reminder.
+    #[serde(default = "default_notifiers")]
+    pub notifiers: Vec<NotifierConfig>,
+    /// TODO keyword sequences used unless a file has its own `#+TODO` lines,
+    /// e.g. `["TODO WAITING | DONE CANCELLED"]`.
+    pub todo_keywords: Option<Vec<String>>,
+    /// Where delivered reminders are recorded, defaults to the XDG state directory.
+    pub state_file: Option<String>,
+    /// Quiet period before a changed file is re-parsed.
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+    /// Templates for `POST /api/capture`, written as `[[capture]]` tables.
+    #[serde(default)]
+    pub capture: Vec<CaptureTemplate>,
+}
+
+fn default_include() -> Vec<String> {
+    vec!["*.org".to_string()]
+}
+
+fn default_notifiers() -> Vec<NotifierConfig> {
+    vec![NotifierConfig::Desktop]
+}
+
+fn default_deadline_warning_days() -> u32 {
+    ReminderOptions::default().deadline_warning_days
+}
+
+fn default_skip_done() -> bool {
+    true
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    300
+}
+
+impl Config {
+    pub fn context(&self) -> Context {
+        match &self.todo_keywords {
+            Some(keywords) => Context::with_todo_keywords(
+                keywords.iter().map(|s| TodoKeywords::parse(s)).collect(),
+            ),
+            None => Context::new(),
+        }
+    }
+
+    /// File a capture template writes to.
+    pub fn capture_path(&self, template: &CaptureTemplate) -> PathBuf {
+        let file = Path::new(&template.file);
+        match self.org_path.first() {
+            Some(root) if file.is_relative() => Path::new(root).join(file),
+            _ => file.to_path_buf(),
+        }
+    }
+
+    pub fn file_filter(&self) -> Result<FileFilter> {
+        FileFilter::new(&self.org_path, &self.include, &self.exclude)
+    }
+
+    pub fn notifiers(&self, events: Sender<OrgEvent>) -> Result<Notifiers> {
+        Notifiers::new(&self.notifiers, events)
+    }
+
+    pub fn state_file(&self) -> Result<PathBuf> {
+        match &self.state_file {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => utils::get_state_file("state.bin"),
+        }
+    }
+
+    pub fn reminder_options(&self) -> Result<ReminderOptions> {
+        let mut opts = ReminderOptions {
+            deadline_warning_days: self.deadline_warning_days,
+            skip_done: self.skip_done,
+            ..Default::default()
+        };
+        if let Some(offsets) = &self.reminder_offsets {
+            opts.offsets = parse_offsets(&offsets.join(" "))?;
+        }
+        Ok(opts)
+    }
 }
 
 pub fn parse_config(path: &str) -> Result<Config> {
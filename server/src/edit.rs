@@ -0,0 +1,323 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, NaiveDateTime};
+use org_parser::{Context, Reminder, TodoKeywords};
+use regex::Regex;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, sync::Mutex};
+use tracing::debug;
+
+/// Serializes read-modify-write cycles on org files within the server.
+static EDIT_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The file is being edited elsewhere, as told by its Emacs lock link.
+#[derive(Debug)]
+pub struct FileLocked {
+    pub path: PathBuf,
+    /// Who holds the lock, as `user@host.pid`.
+    pub owner: String,
+}
+
+impl fmt::Display for FileLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is locked by {}", self.path.display(), self.owner)
+    }
+}
+
+impl std::error::Error for FileLocked {}
+
+/// Emacs-style lock on a file: a `.#name` symlink next to it pointing at
+/// `user@host.pid`, so Emacs asks before modifying a buffer we are writing.
+/// Removed on drop.
+struct FileLock {
+    link: Option<PathBuf>,
+}
+
+impl FileLock {
+    #[cfg(unix)]
+    fn acquire(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("invalid path {:?}", path))?;
+        let link = path.with_file_name(format!(".#{}", name.to_string_lossy()));
+        let user = std::env::var("USER").unwrap_or_else(|_| crate::utils::APP_NAME.to_string());
+        let host = hostname::get()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "localhost".to_string());
+        let owner = format!("{}@{}.{}", user, host, std::process::id());
+        let mut took_over = false;
+        loop {
+            match std::os::unix::fs::symlink(&owner, &link) {
+                Ok(()) => return Ok(FileLock { link: Some(link) }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = std::fs::read_link(&link)
+                        .map(|target| target.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    // a lock left behind by a crashed process on this host is taken over once
+                    if !took_over && is_stale(&holder, &host) {
+                        debug!("take over stale lock {:?} of {}", link, holder);
+                        std::fs::remove_file(&link)?;
+                        took_over = true;
+                        continue;
+                    }
+                    return Err(FileLocked {
+                        path: path.to_path_buf(),
+                        owner: holder,
+                    }
+                    .into());
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn acquire(_path: &Path) -> Result<Self> {
+        Ok(FileLock { link: None })
+    }
+}
+
+/// Whether the lock `owner`, `user@host.pid` optionally followed by `:boot-time` as
+/// Emacs writes it, belongs to a process on `host` that no longer runs.
+#[cfg(unix)]
+fn is_stale(owner: &str, host: &str) -> bool {
+    let Some((_, rest)) = owner.split_once('@') else {
+        return false;
+    };
+    let rest = rest.split(':').next().unwrap_or_default();
+    let Some((owner_host, pid)) = rest.rsplit_once('.') else {
+        return false;
+    };
+    let Ok(pid) = pid.parse::<libc::pid_t>() else {
+        return false;
+    };
+    if owner_host != host || pid <= 0 {
+        return false;
+    }
+    // signal 0 only checks for the process; EPERM means it runs as another user
+    let alive = unsafe { libc::kill(pid, 0) } == 0
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    !alive
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(link) = &self.link {
+            let _ = std::fs::remove_file(link);
+        }
+    }
+}
+
+/// Rewrite `path` with `f`, which gets the current content and returns the new
+/// one. Edits are serialized within the server and refused while the file is
+/// locked by Emacs. A missing file reads as empty when `create` is set.
+///
+/// Symlinks are followed, so the file they point at is replaced and keeps its
+/// permissions.
+pub async fn modify_file<T>(
+    path: &Path,
+    create: bool,
+    f: impl FnOnce(&str) -> Result<(String, T)>,
+) -> Result<T> {
+    let _guard = EDIT_LOCK.lock().await;
+    let (path, metadata) = match fs::canonicalize(path).await {
+        Ok(path) => {
+            let metadata = fs::metadata(&path).await?;
+            (path, Some(metadata))
+        }
+        Err(err) if create && err.kind() == io::ErrorKind::NotFound => (path.to_path_buf(), None),
+        Err(err) => return Err(err.into()),
+    };
+    let _lock = FileLock::acquire(&path)?;
+    let content = match &metadata {
+        Some(_) => fs::read_to_string(&path).await?,
+        None => String::new(),
+    };
+    let (content, res) = f(&content)?;
+    let tmp = path.with_extension("org.tmp");
+    fs::write(&tmp, content).await?;
+    if let Some(metadata) = metadata {
+        fs::set_permissions(&tmp, metadata.permissions()).await?;
+        // best effort, changing the owner needs privileges the server usually lacks
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let _ = std::os::unix::fs::chown(&tmp, Some(metadata.uid()), Some(metadata.gid()));
+        }
+    }
+    fs::rename(&tmp, &path).await?;
+    Ok(res)
+}
+
+/// Done keyword of the sequence `todo` belongs to.
+fn done_keyword<'a>(keywords: &'a [TodoKeywords], todo: Option<&str>) -> Option<&'a str> {
+    let seq = todo
+        .and_then(|todo| keywords.iter().find(|seq| seq.contains(todo)))
+        .or_else(|| keywords.first())?;
+    seq.done.first().map(|s| s.as_str())
+}
+
+/// `content` with the heading on `line` marked done.
+///
+/// Repeated items stay open and their planning timestamps move to the next
+/// repetition instead, like org-mode does.
+pub fn mark_done_content(
+    content: &str,
+    line: usize,
+    title: &str,
+    ctx: &Context,
+    now: NaiveDateTime,
+) -> Result<String> {
+    let mut ctx = ctx.clone();
+    let org = org_parser::parse(&mut ctx, content)?;
+    let sec = org
+        .section_at(line)
+        .filter(|sec| sec.title == title)
+        .ok_or_else(|| anyhow!("heading {:?} not found on line {}", title, line))?;
+    if sec.done {
+        bail!("heading {:?} is already done", title);
+    }
+
+    let mut res = content.to_string();
+    let mut repeated: Vec<_> = sec
+        .scheduling
+        .iter()
+        .filter_map(|sch| {
+            let ts = sch.timestamp();
+            ts.repeat(now).map(|next| (ts.span, next))
+        })
+        .collect();
+    if !repeated.is_empty() {
+        // splice from the back so the spans before stay valid
+        repeated.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+        for (span, next) in repeated {
+            res.replace_range(span.range(), &next.to_string());
+        }
+    } else {
+        let done = done_keyword(&org.todo_keywords, sec.todo.as_deref())
+            .ok_or_else(|| anyhow!("no done keyword"))?;
+        let text = &content[sec.headline.range()];
+        let headline = Regex::new(r"^(\*+[ \t]+)(\S+)")?;
+        let Some(caps) = headline.captures(text) else {
+            bail!("line {} is not a headline", line);
+        };
+        let stars = &caps[1];
+        let next = match &sec.todo {
+            Some(todo) if &caps[2] == todo => {
+                format!("{}{}{}", stars, done, &text[caps[0].len()..])
+            }
+            _ => format!("{}{} {}", stars, done, &text[stars.len()..]),
+        };
+        res.replace_range(sec.headline.range(), &next);
+    }
+    Ok(res)
+}
+
+/// Mark the heading `reminder` comes from done in its file.
+pub async fn mark_done(reminder: &Reminder, ctx: &Context) -> Result<()> {
+    let path = reminder
+        .file
+        .as_deref()
+        .ok_or_else(|| anyhow!("reminder without file"))?;
+    let now = Local::now().naive_local();
+    modify_file(Path::new(path), false, |content| {
+        Ok((
+            mark_done_content(content, reminder.line, &reminder.title, ctx, now)?,
+            (),
+        ))
+    })
+    .await?;
+    debug!("mark done: {:?} {:?}", path, reminder.title);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2024-03-20 12:00", "%F %R").unwrap()
+    }
+
+    #[test]
+    fn test_mark_done() {
+        let content = "#+TODO: TODO WAIT | DONE CANCELLED\n* Project\n** WAIT [#A] Call Bob :work:\nSCHEDULED: <2024-03-20 Wed 10:00>\n";
+        let ctx = Context::new();
+        let res = mark_done_content(content, 3, "Call Bob", &ctx, now()).unwrap();
+        assert_eq!(
+            "#+TODO: TODO WAIT | DONE CANCELLED\n* Project\n** DONE [#A] Call Bob :work:\nSCHEDULED: <2024-03-20 Wed 10:00>\n",
+            res
+        );
+
+        let content = "* Call Bob\nSCHEDULED: <2024-03-20 Wed 10:00>";
+        let res = mark_done_content(content, 1, "Call Bob", &ctx, now()).unwrap();
+        assert_eq!("* DONE Call Bob\nSCHEDULED: <2024-03-20 Wed 10:00>", res);
+
+        assert!(mark_done_content(content, 1, "Call Alice", &ctx, now()).is_err());
+        // only the headline is touched, line endings included
+        let content = "* TODO Call Bob\r\n* TODO Call Alice\r\nText\r\n";
+        let res = mark_done_content(content, 2, "Call Alice", &ctx, now()).unwrap();
+        assert_eq!("* TODO Call Bob\r\n* DONE Call Alice\r\nText\r\n", res);
+        let content = "* DONE Call Bob\n";
+        assert!(mark_done_content(content, 1, "Call Bob", &ctx, now()).is_err());
+    }
+
+    #[test]
+    fn test_mark_done_repeated() {
+        let content = "* TODO Water plants\nSCHEDULED: <2024-03-18 Mon ++1w> DEADLINE: <2024-03-19 Tue .+1d>\n* TODO Next\n";
+        let ctx = Context::new();
+        let res = mark_done_content(content, 1, "Water plants", &ctx, now()).unwrap();
+        assert_eq!(
+            "* TODO Water plants\nSCHEDULED: <2024-03-25 Mon ++1w> DEADLINE: <2024-03-21 Thu .+1d>\n* TODO Next\n",
+            res
+        );
+
+        // only the planning stamps move, not the same text elsewhere in the entry
+        let content = "* TODO Review <2024-03-18 Mon +1w>\nSCHEDULED: <2024-03-18 Mon +1w>\n:PROPERTIES:\n:LAST: <2024-03-18 Mon +1w>\n:END:\nDEADLINE: <2024-03-18 Mon +1w>\n";
+        let res =
+            mark_done_content(content, 1, "Review <2024-03-18 Mon +1w>", &ctx, now()).unwrap();
+        assert_eq!(
+            "* TODO Review <2024-03-18 Mon +1w>\nSCHEDULED: <2024-03-25 Mon +1w>\n:PROPERTIES:\n:LAST: <2024-03-18 Mon +1w>\n:END:\nDEADLINE: <2024-03-25 Mon +1w>\n",
+            res
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stale_lock() {
+        use std::os::unix::fs::symlink;
+
+        let dir = crate::fixtures::temp_dir("edit-lock");
+        let path = dir.join("todo.org");
+        let link = dir.join(".#todo.org");
+        std::fs::write(&path, "* TODO Task\n").unwrap();
+        let host = hostname::get().unwrap().to_string_lossy().into_owned();
+        let edit = || modify_file(&path, false, |c| Ok((format!("{}* New\n", c), ())));
+
+        // held by a live process, here or on another host
+        for owner in [
+            format!("me@{}.{}", host, std::process::id()),
+            "me@elsewhere.example.1".to_string(),
+        ] {
+            symlink(&owner, &link).unwrap();
+            let err = edit().await.unwrap_err();
+            assert_eq!(owner, err.downcast_ref::<FileLocked>().unwrap().owner);
+            std::fs::remove_file(&link).unwrap();
+        }
+
+        // left behind by a process that has exited
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        symlink(format!("me@{}.{}:1700000000", host, pid), &link).unwrap();
+        edit().await.unwrap();
+        assert_eq!(
+            "* TODO Task\n* New\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+        assert!(std::fs::symlink_metadata(&link).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
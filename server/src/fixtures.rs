@@ -0,0 +1,71 @@
+//! Test fixtures shared across modules.
+
+use crate::message::Message;
+use org_parser::{Context, Org, Reminder, ReminderOptions};
+use std::{fs, path::PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+/// Parse `content` as if it was read from `file`.
+pub fn org(file: &str, content: &str) -> Org {
+    let mut ctx = Context::new();
+    let mut org = org_parser::parse(&mut ctx, content).unwrap();
+    org.filename = Some(file.to_string());
+    org
+}
+
+/// The first reminder of the headings in `content`, with the default options.
+pub fn reminder(content: &str) -> Reminder {
+    let mut ctx = Context::new();
+    let org = org_parser::parse(&mut ctx, content).unwrap();
+    org.get_reminders(&ReminderOptions::default()).remove(0)
+}
+
+pub fn message(body: &str) -> Message {
+    Message {
+        summary: "Org Reminder".to_string(),
+        body: body.to_string(),
+    }
+}
+
+/// A fresh, empty directory under the system temp dir, unique to this process and `name`.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("org-server-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Accept one HTTP request, answer 200 and hand back the raw request.
+pub async fn mock_server() -> (String, oneshot::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            let req = String::from_utf8_lossy(&buf).to_string();
+            if let Some((head, body)) = req.split_once("\r\n\r\n") {
+                let len = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length: "))
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if n == 0 || body.len() >= len {
+                    break;
+                }
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        let _ = tx.send(String::from_utf8_lossy(&buf).to_string());
+    });
+    (url, rx)
+}
@@ -0,0 +1,149 @@
+use crate::search::{SearchHit, SearchIndex};
+use org_parser::{Org, Section};
+use parking_lot::RwLock;
+use std::{collections::HashMap, sync::Arc};
+
+/// Where an `ID` points: a whole file, or the heading starting on `line`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: Option<usize>,
+}
+
+#[derive(Default)]
+struct Inner {
+    docs: HashMap<String, Arc<Org>>,
+    ids: HashMap<String, Location>,
+    search: SearchIndex,
+}
+
+impl Inner {
+    fn remove(&mut self, path: &str) -> Vec<String> {
+        let dir = format!("{}/", path.trim_end_matches('/'));
+        let removed: Vec<String> = self
+            .docs
+            .keys()
+            .filter(|file| *file == path || file.starts_with(&dir))
+            .cloned()
+            .collect();
+        for file in &removed {
+            self.docs.remove(file);
+            self.search.remove(file);
+        }
+        self.ids.retain(|_, loc| !removed.contains(&loc.file));
+        removed
+    }
+}
+
+fn collect_ids(sections: &[Section], file: &str, ids: &mut HashMap<String, Location>) {
+    for sec in sections {
+        if let Some(id) = sec.property("ID") {
+            ids.insert(
+                id.to_string(),
+                Location {
+                    file: file.to_string(),
+                    line: Some(sec.line),
+                },
+            );
+        }
+        collect_ids(&sec.sections, file, ids);
+    }
+}
+
+/// Latest parsed state of every org file, written by the scanner and watcher and
+/// read by the reminder checker and the web server.
+#[derive(Default)]
+pub struct Index {
+    inner: RwLock<Inner>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add or replace a document, keyed by its file name.
+    pub fn insert(&self, org: Org) -> Arc<Org> {
+        let file = org.filename.clone().unwrap_or_default();
+        let mut ids = HashMap::new();
+        if let Some(id) = &org.id {
+            ids.insert(
+                id.clone(),
+                Location {
+                    file: file.clone(),
+                    line: None,
+                },
+            );
+        }
+        collect_ids(&org.sections, &file, &mut ids);
+
+        let org = Arc::new(org);
+        let mut inner = self.inner.write();
+        inner.ids.retain(|_, loc| loc.file != file);
+        inner.ids.extend(ids);
+        inner.search.insert(&org);
+        inner.docs.insert(file, org.clone());
+        org
+    }
+
+    /// Drop the document at `path`, or every document under it when it is a
+    /// directory. Returns the files removed.
+    pub fn remove(&self, path: &str) -> Vec<String> {
+        self.inner.write().remove(path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<Arc<Org>> {
+        self.inner.read().docs.get(path).cloned()
+    }
+
+    pub fn documents(&self) -> Vec<Arc<Org>> {
+        self.inner.read().docs.values().cloned().collect()
+    }
+
+    /// Headings matching `query` across all documents, best match first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        self.inner.read().search.search(query, limit)
+    }
+
+    /// File and heading an `ID` property belongs to.
+    pub fn locate(&self, id: &str) -> Option<Location> {
+        self.inner.read().ids.get(id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::org;
+
+    #[test]
+    fn test_index() {
+        let index = Index::new();
+        index.insert(org(
+            "/notes/a.org",
+            ":PROPERTIES:\n:ID: file-a\n:END:\n* Task\n:PROPERTIES:\n:ID: task-a\n:END:\n",
+        ));
+        index.insert(org(
+            "/notes/sub/b.org",
+            "* Other\n:PROPERTIES:\n:ID: task-b\n:END:\n",
+        ));
+        assert_eq!(2, index.documents().len());
+        assert_eq!(
+            Some(Location {
+                file: "/notes/a.org".to_string(),
+                line: Some(4)
+            }),
+            index.locate("task-a")
+        );
+        assert_eq!(None, index.locate("file-a").and_then(|loc| loc.line));
+
+        // ids of the previous version are dropped
+        index.insert(org("/notes/a.org", "* Task\n"));
+        assert_eq!(None, index.locate("task-a"));
+        assert!(index.locate("task-b").is_some());
+
+        assert_eq!(vec!["/notes/sub/b.org"], index.remove("/notes/sub"));
+        assert_eq!(None, index.locate("task-b"));
+        assert!(index.get("/notes/a.org").is_some());
+    }
+}
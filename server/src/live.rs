@@ -0,0 +1,34 @@
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// Events buffered for each subscriber before it starts missing them.
+const CAPACITY: usize = 256;
+
+pub type LiveSender = broadcast::Sender<LiveEvent>;
+
+/// Updates streamed to web clients as they happen.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum LiveEvent {
+    /// A reminder was delivered, with the same fields webhooks get.
+    ReminderFired(Value),
+    /// A file was (re-)indexed after a change on disk.
+    DocumentChanged { file: String },
+    /// A file was deleted or moved away.
+    DocumentRemoved { file: String },
+}
+
+impl LiveEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiveEvent::ReminderFired(_) => "reminder-fired",
+            LiveEvent::DocumentChanged { .. } => "document-changed",
+            LiveEvent::DocumentRemoved { .. } => "document-removed",
+        }
+    }
+}
+
+pub fn channel() -> LiveSender {
+    broadcast::channel(CAPACITY).0
+}
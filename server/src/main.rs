@@ -1,13 +1,22 @@
 use anyhow::Result;
 use clap::Parser;
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 use tracing::debug;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod capture;
 mod config;
+mod edit;
+#[cfg(test)]
+mod fixtures;
+mod index;
+mod live;
+mod message;
 mod notification;
 mod parse;
 mod reminders;
+mod search;
+mod state;
 mod utils;
 mod watcher;
 mod web;
@@ -33,15 +42,24 @@ async fn main() -> Result<()> {
     debug!("load config path: {:?}", config_path);
     let config = config::parse_config(&config_path.to_string_lossy())?;
 
+    let filter = Arc::new(config.file_filter()?);
+    let index = Arc::new(index::Index::new());
+    let live = live::channel();
     let (tx, rx) = tokio::sync::mpsc::channel(1024);
 
-    watcher::watch_files(&config, tx.clone())?;
+    watcher::watch_files(
+        &config,
+        filter.clone(),
+        index.clone(),
+        live.clone(),
+        tx.clone(),
+    )?;
 
     // start checker
-    reminders::start_check(rx).await?;
-    reminders::scan(&config, tx.clone())?;
+    reminders::start_check(&config, index.clone(), live.clone(), tx.clone(), rx).await?;
+    reminders::scan(&config, filter, index.clone(), tx.clone())?;
 
-    web::run_server(config.server_port).await?;
+    web::run_server(&config, index, live).await?;
     Ok(())
 }
 
@@ -0,0 +1,252 @@
+use org_parser::{Reminder, ReminderKind, Scheduling};
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    En,
+    #[default]
+    Ja,
+}
+
+/// Message templates, see `render` for the available placeholders.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MessageConfig {
+    #[serde(default)]
+    pub locale: Locale,
+    /// Notification summary.
+    pub summary: Option<String>,
+    /// Body for reminders firing ahead of an item.
+    pub upcoming: Option<String>,
+    /// Body for reminders firing at the time of an item.
+    pub due: Option<String>,
+    /// Body for deadline warnings.
+    pub warning: Option<String>,
+    /// Body for reminders that came due while the server was not running.
+    pub missed: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub summary: String,
+    pub body: String,
+}
+
+struct Templates {
+    summary: &'static str,
+    upcoming: &'static str,
+    due: &'static str,
+    warning: &'static str,
+    missed: &'static str,
+}
+
+const EN: Templates = Templates {
+    summary: "Org Reminder",
+    upcoming: "{kind} in {offset}: {title} ({time})",
+    due: "{kind} now: {title} ({time})",
+    warning: "Deadline in {offset}: {title} ({time})",
+    missed: "Missed while offline: {title} ({time})",
+};
+
+const JA: Templates = Templates {
+    summary: "Emacs Org Remainder",
+    upcoming: "このイベントまであと{offset}: {title}",
+    due: "このイベントの時間です: {title}",
+    warning: "締め切りまであと{offset}: {title}",
+    missed: "オフライン中に過ぎたイベント: {title} ({time})",
+};
+
+impl Locale {
+    fn templates(&self) -> &'static Templates {
+        match self {
+            Locale::En => &EN,
+            Locale::Ja => &JA,
+        }
+    }
+
+    fn kind(&self, reminder: &Reminder) -> &'static str {
+        match (self, &reminder.scheduling) {
+            (Locale::En, Scheduling::Scheduled(_)) => "Scheduled",
+            (Locale::En, Scheduling::Deadline(_)) => "Deadline",
+            (Locale::Ja, Scheduling::Scheduled(_)) => "予定",
+            (Locale::Ja, Scheduling::Deadline(_)) => "締め切り",
+        }
+    }
+
+    fn duration(&self, d: Duration) -> String {
+        let min = d.as_secs() / 60;
+        let (value, unit) = if min >= 60 * 24 && min.is_multiple_of(60 * 24) {
+            (min / (60 * 24), 0)
+        } else if min >= 60 && min.is_multiple_of(60) {
+            (min / 60, 1)
+        } else {
+            (min, 2)
+        };
+        match self {
+            Locale::En => {
+                let unit = ["day", "hour", "minute"][unit];
+                if value == 1 {
+                    format!("{} {}", value, unit)
+                } else {
+                    format!("{} {}s", value, unit)
+                }
+            }
+            Locale::Ja => format!("{}{}", value, ["日", "時間", "分"][unit]),
+        }
+    }
+}
+
+/// Replace `{title}`, `{time}`, `{offset}`, `{file}`, `{tags}` and `{kind}` in `template`.
+///
+/// Placeholders are expanded in a single pass, so substituted text is never expanded again.
+/// Unknown placeholders are kept as they are.
+pub fn render(template: &str, locale: Locale, reminder: &Reminder) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        match placeholder(&rest[1..end], locale, reminder) {
+            Some(value) => res.push_str(&value),
+            None => res.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    res
+}
+
+fn placeholder(name: &str, locale: Locale, reminder: &Reminder) -> Option<String> {
+    let value = match name {
+        "title" => reminder.title.trim().to_string(),
+        "time" if reminder.scheduling.timestamp().has_time() => {
+            reminder.at.format("%F %R").to_string()
+        }
+        "time" => reminder.at.format("%F").to_string(),
+        "offset" => locale.duration(reminder.offset()),
+        "file" => reminder.file.clone().unwrap_or_default(),
+        "tags" => reminder.tags.join(", "),
+        "kind" => locale.kind(reminder).to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+pub fn format(config: &MessageConfig, reminder: &Reminder) -> Message {
+    let defaults = config.locale.templates();
+    let summary = config.summary.as_deref().unwrap_or(defaults.summary);
+    let body = match reminder.kind {
+        ReminderKind::DeadlineWarning => config.warning.as_deref().unwrap_or(defaults.warning),
+        ReminderKind::Upcoming if reminder.offset().is_zero() => {
+            config.due.as_deref().unwrap_or(defaults.due)
+        }
+        ReminderKind::Upcoming => config.upcoming.as_deref().unwrap_or(defaults.upcoming),
+    };
+    Message {
+        summary: render(summary, config.locale, reminder),
+        body: render(body, config.locale, reminder),
+    }
+}
+
+/// Catch-up message for a reminder that came due while the server was not running.
+pub fn format_missed(config: &MessageConfig, reminder: &Reminder) -> Message {
+    let defaults = config.locale.templates();
+    let summary = config.summary.as_deref().unwrap_or(defaults.summary);
+    let body = config.missed.as_deref().unwrap_or(defaults.missed);
+    Message {
+        summary: render(summary, config.locale, reminder),
+        body: render(body, config.locale, reminder),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use org_parser::{Context, ReminderOptions};
+
+    fn reminder(content: &str) -> Reminder {
+        let mut ctx = Context::new();
+        let mut org = org_parser::parse(&mut ctx, content).unwrap();
+        org.filename = Some("todo.org".to_string());
+        let opts = ReminderOptions {
+            offsets: vec![Duration::from_secs(90 * 60)],
+            ..ReminderOptions::default()
+        };
+        org.get_reminders(&opts).remove(0)
+    }
+
+    #[test]
+    fn test_render() {
+        let rem = reminder("* Call Bob :work:phone:\nSCHEDULED: <2099-03-04 Wed 10:00>\n");
+        let en = |template| render(template, Locale::En, &rem);
+        assert_eq!("Call Bob", en("{title}"));
+        assert_eq!("2099-03-04 10:00", en("{time}"));
+        assert_eq!("90 minutes", en("{offset}"));
+        assert_eq!("todo.org", en("{file}"));
+        assert_eq!("work, phone", en("{tags}"));
+        assert_eq!("Scheduled", en("{kind}"));
+        assert_eq!("{unknown} {title", en("{unknown} {title"));
+
+        let rem = reminder("* Call Bob\nSCHEDULED: <2099-03-04 Wed>\n");
+        assert_eq!("2099-03-04", render("{time}", Locale::En, &rem));
+        assert_eq!("予定", render("{kind}", Locale::Ja, &rem));
+        assert_eq!("90分", render("{offset}", Locale::Ja, &rem));
+
+        let rem = reminder("* Call Bob\nDEADLINE: <2099-03-04 Wed 10:00>\n");
+        assert_eq!("Deadline", render("{kind}", Locale::En, &rem));
+        assert_eq!("締め切り", render("{kind}", Locale::Ja, &rem));
+    }
+
+    #[test]
+    fn test_render_once() {
+        let rem = reminder("* Rename {file} to {tags}\nSCHEDULED: <2099-03-04 Wed 10:00>\n");
+        assert_eq!(
+            "Rename {file} to {tags} (todo.org)",
+            render("{title} ({file})", Locale::En, &rem)
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let rem = reminder("* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n");
+
+        let config = MessageConfig::default();
+        assert_eq!(Locale::Ja, config.locale);
+        let msg = format(&config, &rem);
+        assert_eq!("Emacs Org Remainder", msg.summary);
+        assert_eq!("このイベントまであと90分: Call Bob", msg.body);
+
+        let config = MessageConfig {
+            locale: Locale::En,
+            ..MessageConfig::default()
+        };
+        let msg = format(&config, &rem);
+        assert_eq!("Org Reminder", msg.summary);
+        assert_eq!(
+            "Scheduled in 90 minutes: Call Bob (2099-03-04 10:00)",
+            msg.body
+        );
+
+        let config = MessageConfig {
+            locale: Locale::En,
+            due: Some("{title} is due".to_string()),
+            ..MessageConfig::default()
+        };
+        let opts = ReminderOptions {
+            offsets: vec![Duration::ZERO],
+            ..ReminderOptions::default()
+        };
+        let mut ctx = Context::new();
+        let org = org_parser::parse(&mut ctx, "* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n");
+        let rem = org.unwrap().get_reminders(&opts).remove(0);
+        assert_eq!("Call Bob is due", format(&config, &rem).body);
+        assert_eq!(
+            "オフライン中に過ぎたイベント: Call Bob (2099-03-04 10:00)",
+            format_missed(&MessageConfig::default(), &rem).body
+        );
+    }
+}
@@ -1,13 +1,161 @@
+use crate::{live::LiveSender, message::Message, reminders::OrgEvent};
 use anyhow::Result;
-use notify_rust::Notification;
-
-pub fn notify(summary: &str, body: &str) -> Result<()> {
-    Notification::new()
-        .summary(summary)
-        .body(body)
-        .icon("emacs")
-        .appname("Emacs Remainder")
-        .timeout(0)
-        .show()?;
-    Ok(())
+use futures::future::{join_all, BoxFuture};
+use org_parser::{Reminder, ReminderKind, Scheduling};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tracing::error;
+
+mod command;
+mod desktop;
+mod email;
+mod live;
+mod push;
+mod stdout;
+mod webhook;
+
+/// A sink reminders are delivered to.
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>>;
+}
+
+/// One entry of the `[[notifiers]]` config list.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    /// Desktop notification with snooze and done actions, the default.
+    Desktop,
+    /// JSON POST to `url`.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Shell command run with `ORG_*` environment variables.
+    Command {
+        command: String,
+    },
+    /// ntfy topic URL, with an optional access token.
+    Ntfy {
+        url: String,
+        token: Option<String>,
+    },
+    /// Gotify server URL and application token.
+    Gotify {
+        url: String,
+        token: String,
+    },
+    /// Mail over SMTP.
+    Email(email::EmailConfig),
+    Stdout,
+    Log,
+}
+
+impl NotifierConfig {
+    fn build(&self, events: &Sender<OrgEvent>) -> Result<Box<dyn Notifier>> {
+        Ok(match self {
+            NotifierConfig::Desktop => Box::new(desktop::Desktop::new(events.clone())),
+            NotifierConfig::Webhook { url, headers } => {
+                Box::new(webhook::Webhook::new(url, headers)?)
+            }
+            NotifierConfig::Command { command } => Box::new(command::Command::new(command)),
+            NotifierConfig::Ntfy { url, token } => {
+                Box::new(push::Push::new(push::Service::Ntfy, url, token.as_deref())?)
+            }
+            NotifierConfig::Gotify { url, token } => {
+                Box::new(push::Push::new(push::Service::Gotify, url, Some(token))?)
+            }
+            NotifierConfig::Email(config) => Box::new(email::Email::new(config)?),
+            NotifierConfig::Stdout => Box::new(stdout::Stdout),
+            NotifierConfig::Log => Box::new(stdout::Log),
+        })
+    }
+}
+
+/// All configured sinks; every reminder goes to each of them.
+pub struct Notifiers {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl Notifiers {
+    /// `events` receives the actions taken on notifications, such as snoozing.
+    pub fn new(configs: &[NotifierConfig], events: Sender<OrgEvent>) -> Result<Self> {
+        let notifiers = configs
+            .iter()
+            .map(|c| c.build(&events))
+            .collect::<Result<_>>()?;
+        Ok(Notifiers { notifiers })
+    }
+
+    /// Also stream every reminder to the web clients listening on `events`.
+    pub fn with_live(mut self, events: LiveSender) -> Self {
+        self.notifiers.push(Box::new(live::Live::new(events)));
+        self
+    }
+
+    pub async fn notify(&self, msg: &Message, reminder: &Reminder) {
+        let results = join_all(self.notifiers.iter().map(|n| n.notify(msg, reminder))).await;
+        for err in results.into_iter().filter_map(|res| res.err()) {
+            error!("NotifyError: {:?}", err);
+        }
+    }
+}
+
+/// JSON form of a reminder, as posted to webhooks and streamed to web clients.
+fn payload(msg: &Message, reminder: &Reminder) -> Value {
+    json!({
+        "summary": msg.summary,
+        "body": msg.body,
+        "title": reminder.title.trim(),
+        "kind": kind(reminder),
+        "at": reminder.at,
+        "datetime": reminder.datetime,
+        "file": reminder.file,
+        "id": reminder.id,
+        "line": reminder.line,
+        "tags": reminder.tags,
+    })
+}
+
+/// Machine readable kind of a reminder, as passed to webhooks and commands.
+fn kind(reminder: &Reminder) -> &'static str {
+    match (reminder.kind, &reminder.scheduling) {
+        (ReminderKind::DeadlineWarning, _) => "deadline-warning",
+        (ReminderKind::Upcoming, Scheduling::Deadline(_)) => "deadline",
+        (ReminderKind::Upcoming, Scheduling::Scheduled(_)) => "scheduled",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{message, reminder, temp_dir};
+    use std::fs;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_failing_notifier() {
+        let dir = temp_dir("notifiers");
+        let out = dir.join("out");
+        let configs = [
+            NotifierConfig::Command {
+                command: "exit 1".to_string(),
+            },
+            NotifierConfig::Webhook {
+                url: "http://127.0.0.1:1/".to_string(),
+                headers: HashMap::new(),
+            },
+            NotifierConfig::Command {
+                command: format!("printf '%s' \"$ORG_TITLE\" > {}", out.display()),
+            },
+        ];
+        let (tx, _rx) = mpsc::channel(1);
+        let notifiers = Notifiers::new(&configs, tx).unwrap();
+        let content = "* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n";
+        notifiers.notify(&message("body"), &reminder(content)).await;
+        assert_eq!("Call Bob", fs::read_to_string(&out).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
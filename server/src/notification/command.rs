@@ -0,0 +1,78 @@
+use super::{kind, Notifier};
+use crate::message::Message;
+use anyhow::{bail, Result};
+use futures::{future::BoxFuture, FutureExt};
+use org_parser::Reminder;
+use tokio::process;
+
+/// Runs `command` with `sh -c`, passing the reminder in `ORG_*` environment variables.
+pub struct Command {
+    command: String,
+}
+
+impl Command {
+    pub fn new(command: &str) -> Self {
+        Command {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Notifier for Command {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let status = process::Command::new("sh")
+                .arg("-c")
+                .arg(&self.command)
+                .env("ORG_SUMMARY", &msg.summary)
+                .env("ORG_BODY", &msg.body)
+                .env("ORG_TITLE", reminder.title.trim())
+                .env("ORG_KIND", kind(reminder))
+                .env("ORG_AT", reminder.at.format("%F %R").to_string())
+                .env("ORG_FILE", reminder.file.as_deref().unwrap_or_default())
+                .env("ORG_ID", reminder.id.as_deref().unwrap_or_default())
+                .env("ORG_TAGS", reminder.tags.join(":"))
+                .status()
+                .await?;
+            if !status.success() {
+                bail!("{:?} exited with {}", self.command, status);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{message, reminder, temp_dir};
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_command() {
+        let dir = temp_dir("command");
+        let out = dir.join("out");
+        let command = Command::new(&format!(
+            "printf '%s|%s|%s|%s|%s' \"$ORG_SUMMARY\" \"$ORG_TITLE\" \"$ORG_KIND\" \"$ORG_AT\" \"$ORG_TAGS\" > {}",
+            out.display()
+        ));
+        let content = "* TODO Call Bob $HOME :work:phone:\nSCHEDULED: <2099-03-04 Wed 10:00>\n";
+        command
+            .notify(&message("Scheduled in 30 minutes"), &reminder(content))
+            .await
+            .unwrap();
+        // values are passed through the environment, never spliced into the command line
+        assert_eq!(
+            "Org Reminder|Call Bob $HOME|scheduled|2099-03-04 10:00|work:phone",
+            fs::read_to_string(&out).unwrap()
+        );
+
+        let failing = Command::new("exit 3");
+        assert!(failing
+            .notify(&message("body"), &reminder(content))
+            .await
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use super::Notifier;
+use crate::{message::Message, reminders::OrgEvent};
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use notify_rust::Notification;
+use org_parser::Reminder;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc::Sender, Semaphore},
+    task,
+};
+
+/// How many notifications may wait for an action at once. Each one holds a blocking
+/// thread until it is acted on or closed; beyond this, notifications come without actions.
+const MAX_WAITING: usize = 16;
+
+/// Desktop notification over the session bus.
+pub struct Desktop {
+    events: Sender<OrgEvent>,
+    waiting: Arc<Semaphore>,
+}
+
+impl Desktop {
+    pub fn new(events: Sender<OrgEvent>) -> Self {
+        Desktop {
+            events,
+            waiting: Arc::new(Semaphore::new(MAX_WAITING)),
+        }
+    }
+}
+
+/// What a notification action asks the checker to do.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn action_event(action: &str, reminder: Reminder) -> Option<OrgEvent> {
+    match action {
+        "snooze-5m" => Some(OrgEvent::Snooze(reminder, Duration::from_secs(5 * 60))),
+        "snooze-1h" => Some(OrgEvent::Snooze(reminder, Duration::from_secs(60 * 60))),
+        "done" => Some(OrgEvent::Done(reminder)),
+        _ => None,
+    }
+}
+
+impl Notifier for Desktop {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>> {
+        let summary = msg.summary.clone();
+        let body = msg.body.clone();
+        let reminder = reminder.clone();
+        let events = self.events.clone();
+        let permit = if reminder.file.is_some() {
+            self.waiting.clone().try_acquire_owned().ok()
+        } else {
+            None
+        };
+        let actionable = permit.is_some();
+        async move {
+            let handle = task::spawn_blocking(move || {
+                let mut notification = Notification::new();
+                notification
+                    .summary(&summary)
+                    .body(&body)
+                    .icon("emacs")
+                    .appname("Emacs Remainder")
+                    .timeout(0);
+                if actionable {
+                    notification
+                        .action("snooze-5m", "Snooze 5m")
+                        .action("snooze-1h", "Snooze 1h")
+                        .action("done", "Done");
+                }
+                notification.show()
+            })
+            .await??;
+
+            #[cfg(all(unix, not(target_os = "macos")))]
+            if let Some(permit) = permit {
+                // waiting blocks until the popup is acted on or closed
+                task::spawn_blocking(move || {
+                    let _permit = permit;
+                    handle.wait_for_action(|action| {
+                        tracing::debug!("notification action: {:?}", action);
+                        if let Some(event) = action_event(action, reminder) {
+                            let _ = events.blocking_send(event);
+                        }
+                    });
+                });
+            }
+            #[cfg(not(all(unix, not(target_os = "macos"))))]
+            let _ = (handle, events, permit);
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,321 @@
+use super::Notifier;
+use crate::message::Message;
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDateTime, NaiveTime};
+use futures::{future::BoxFuture, FutureExt};
+use lettre::{
+    message::Mailbox,
+    transport::smtp::{authentication::Credentials, client::Tls},
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use org_parser::Reminder;
+use serde::Deserialize;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::{task, time};
+use tracing::error;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Upgrade the connection with STARTTLS, usually on port 587.
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+    /// Plain text, only for local relays.
+    None,
+}
+
+/// SMTP password, kept out of the logged config.
+#[derive(Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Password(String);
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    pub username: Option<String>,
+    pub password: Option<Password>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Time of day, e.g. `"08:00"`, to send the reminders collected since the last
+    /// digest in one mail instead of one mail per reminder.
+    pub digest: Option<String>,
+}
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Mailer {
+    async fn send(&self, subject: &str, body: String) -> Result<()> {
+        let mut builder = lettre::Message::builder()
+            .from(self.from.clone())
+            .subject(subject);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        self.transport.send(builder.body(body)?).await?;
+        Ok(())
+    }
+}
+
+/// Mails reminders over SMTP, either right away or as a daily digest.
+pub struct Email {
+    mailer: Arc<Mailer>,
+    /// Messages waiting for the next digest.
+    queue: Option<Arc<Mutex<Vec<Message>>>>,
+}
+
+impl Email {
+    pub fn new(config: &EmailConfig) -> Result<Self> {
+        let mut builder = match config.security {
+            Security::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            Security::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host).tls(Tls::None)
+            }
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.0.clone()));
+        }
+        let mailer = Arc::new(Mailer {
+            transport: builder.build(),
+            from: config.from.parse()?,
+            to: config
+                .to
+                .iter()
+                .map(|to| to.parse())
+                .collect::<Result<_, _>>()?,
+        });
+
+        let queue = match &config.digest {
+            Some(at) => {
+                let at = NaiveTime::parse_from_str(at, "%H:%M")
+                    .map_err(|_| anyhow!("invalid digest time: {}", at))?;
+                let queue = Arc::new(Mutex::new(vec![]));
+                task::spawn(send_digests(mailer.clone(), queue.clone(), at));
+                Some(queue)
+            }
+            None => None,
+        };
+        Ok(Email { mailer, queue })
+    }
+}
+
+impl Notifier for Email {
+    fn notify<'a>(
+        &'a self,
+        msg: &'a Message,
+        _reminder: &'a Reminder,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            match &self.queue {
+                Some(queue) => {
+                    if let Ok(mut queue) = queue.lock() {
+                        queue.push(msg.clone());
+                    }
+                }
+                None => self.mailer.send(&msg.summary, msg.body.clone()).await?,
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Body of a digest mail, one line per message.
+fn digest_body(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|msg| format!("- {}\n", msg.body))
+        .collect()
+}
+
+/// The first time of day `at` after `now`, tomorrow if it has already passed today.
+fn next_digest(now: NaiveDateTime, at: NaiveTime) -> NaiveDateTime {
+    let next = now.date().and_time(at);
+    if next <= now {
+        next + chrono::Duration::days(1)
+    } else {
+        next
+    }
+}
+
+/// Send the queued messages every day at `at`. Messages still queued when the
+/// server stops are lost.
+async fn send_digests(mailer: Arc<Mailer>, queue: Arc<Mutex<Vec<Message>>>, at: NaiveTime) {
+    loop {
+        let now = Local::now().naive_local();
+        let next = next_digest(now, at);
+        time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+        let messages = match queue.lock() {
+            Ok(mut queue) => std::mem::take(&mut *queue),
+            Err(_) => continue,
+        };
+        if messages.is_empty() {
+            continue;
+        }
+        let subject = format!("Org reminders {}", next.format("%F"));
+        if let Err(err) = mailer.send(&subject, digest_body(&messages)).await {
+            error!("NotifyError: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{message, reminder};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    const CONTENT: &str = "* TODO Pay rent\nDEADLINE: <2099-03-04 Wed>\n";
+
+    /// Minimal SMTP server accepting one mail and handing back its DATA.
+    async fn smtp_sink() -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 sink\r\n").await.unwrap();
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        write.write_all(b"250 OK\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default() {
+                    "EHLO" | "HELO" => b"250 sink\r\n",
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                write.write_all(reply).await.unwrap();
+            }
+            let _ = tx.send(data);
+        });
+        (port, rx)
+    }
+
+    fn config(port: u16) -> EmailConfig {
+        EmailConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: Security::None,
+            username: None,
+            password: None,
+            from: "Org <org@example.com>".to_string(),
+            to: vec!["me@example.com".to_string()],
+            digest: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (port, rx) = smtp_sink().await;
+        let email = Email::new(&config(port)).unwrap();
+        email
+            .notify(
+                &message("Deadline in 14 days: Pay rent"),
+                &reminder(CONTENT),
+            )
+            .await
+            .unwrap();
+        drop(email);
+
+        let data = rx.await.unwrap();
+        assert!(data.contains("Subject: Org Reminder\n"));
+        assert!(data.contains("To: me@example.com\n"));
+        assert!(data.contains("Deadline in 14 days: Pay rent"));
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = config(listener.local_addr().unwrap().port());
+        config.digest = Some("08:00".to_string());
+        let email = Email::new(&config).unwrap();
+        let r = reminder(CONTENT);
+        email.notify(&message("first"), &r).await.unwrap();
+        email.notify(&message("second"), &r).await.unwrap();
+
+        // nothing goes out before the digest time
+        let accepted = time::timeout(Duration::from_millis(300), listener.accept()).await;
+        assert!(accepted.is_err());
+        assert_eq!(2, email.queue.as_ref().unwrap().lock().unwrap().len());
+        assert_eq!(
+            "- first\n- second\n",
+            digest_body(&[message("first"), message("second")])
+        );
+    }
+
+    #[test]
+    fn test_next_digest() {
+        let at = NaiveTime::from_hms_opt(8, 0, 0).unwrap();
+        let dt = |s: &str| NaiveDateTime::parse_from_str(s, "%F %R").unwrap();
+        assert_eq!(
+            dt("2024-03-04 08:00"),
+            next_digest(dt("2024-03-04 07:59"), at)
+        );
+        // already passed, or due right now: tomorrow
+        assert_eq!(
+            dt("2024-03-05 08:00"),
+            next_digest(dt("2024-03-04 08:00"), at)
+        );
+        assert_eq!(
+            dt("2024-03-05 08:00"),
+            next_digest(dt("2024-03-04 23:30"), at)
+        );
+        // across the end of the month
+        assert_eq!(
+            dt("2024-03-01 08:00"),
+            next_digest(dt("2024-02-29 12:00"), at)
+        );
+    }
+
+    #[test]
+    fn test_invalid_digest() {
+        let mut config = config(25);
+        config.digest = Some("8am".to_string());
+        assert!(Email::new(&config).is_err());
+    }
+}
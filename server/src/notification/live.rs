@@ -0,0 +1,29 @@
+use super::{payload, Notifier};
+use crate::{
+    live::{LiveEvent, LiveSender},
+    message::Message,
+};
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use org_parser::Reminder;
+
+/// Streams reminders to connected web clients.
+pub struct Live {
+    events: LiveSender,
+}
+
+impl Live {
+    pub fn new(events: LiveSender) -> Self {
+        Live { events }
+    }
+}
+
+impl Notifier for Live {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>> {
+        // no subscribers is not an error
+        let _ = self
+            .events
+            .send(LiveEvent::ReminderFired(payload(msg, reminder)));
+        async { Ok(()) }.boxed()
+    }
+}
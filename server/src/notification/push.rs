@@ -0,0 +1,151 @@
+use super::Notifier;
+use crate::message::Message;
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use org_parser::Reminder;
+use reqwest::Client;
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// `url` is the topic URL, e.g. `https://ntfy.sh/reminders`.
+    Ntfy,
+    /// `url` is the server URL; messages go to `/message`.
+    Gotify,
+}
+
+/// Push notification through a self-hosted ntfy or Gotify server.
+pub struct Push {
+    client: Client,
+    service: Service,
+    url: String,
+    token: Option<String>,
+}
+
+impl Push {
+    pub fn new(service: Service, url: &str, token: Option<&str>) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(Push {
+            client,
+            service,
+            url: url.trim_end_matches('/').to_string(),
+            token: token.map(|t| t.to_string()),
+        })
+    }
+}
+
+/// ntfy priority (1-5) for an org priority cookie. No cookie means `B`, org's default.
+fn ntfy_priority(priority: Option<char>) -> u8 {
+    match priority.unwrap_or('B') {
+        'A' => 4,
+        'B' => 3,
+        'C' => 2,
+        _ => 1,
+    }
+}
+
+/// Gotify priority (0-10) for an org priority cookie.
+fn gotify_priority(priority: Option<char>) -> u8 {
+    match priority.unwrap_or('B') {
+        'A' => 8,
+        'B' => 5,
+        'C' => 2,
+        _ => 1,
+    }
+}
+
+impl Notifier for Push {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let req = match self.service {
+                Service::Ntfy => {
+                    let mut req = self
+                        .client
+                        .post(&self.url)
+                        .header("Title", &msg.summary)
+                        .header("Priority", ntfy_priority(reminder.priority).to_string())
+                        .body(msg.body.clone());
+                    if !reminder.tags.is_empty() {
+                        req = req.header("Tags", reminder.tags.join(","));
+                    }
+                    if let Some(token) = &self.token {
+                        req = req.bearer_auth(token);
+                    }
+                    req
+                }
+                Service::Gotify => {
+                    let mut req = self
+                        .client
+                        .post(format!("{}/message", self.url))
+                        .json(&json!({
+                            "title": msg.summary,
+                            "message": msg.body,
+                            "priority": gotify_priority(reminder.priority),
+                            "extras": {
+                                "org::tags": reminder.tags,
+                            },
+                        }));
+                    if let Some(token) = &self.token {
+                        req = req.header("X-Gotify-Key", token);
+                    }
+                    req
+                }
+            };
+            req.send().await?.error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{message, mock_server, reminder};
+
+    const CONTENT: &str = "* TODO [#A] Call Bob :work:phone:\nSCHEDULED: <2099-03-04 Wed 10:00>\n";
+    const BODY: &str = "Scheduled in 30 minutes: Call Bob";
+
+    #[tokio::test]
+    async fn test_ntfy() {
+        let (url, rx) = mock_server().await;
+        let push = Push::new(Service::Ntfy, &format!("{}/reminders", url), Some("tk")).unwrap();
+        push.notify(&message(BODY), &reminder(CONTENT))
+            .await
+            .unwrap();
+
+        let req = rx.await.unwrap();
+        assert!(req.starts_with("POST /reminders "));
+        assert!(req.contains("title: Org Reminder\r\n"));
+        assert!(req.contains("priority: 4\r\n"));
+        assert!(req.contains("tags: work,phone\r\n"));
+        assert!(req.contains("authorization: Bearer tk\r\n"));
+        assert!(req.ends_with("\r\n\r\nScheduled in 30 minutes: Call Bob"));
+    }
+
+    #[tokio::test]
+    async fn test_gotify() {
+        let (url, rx) = mock_server().await;
+        let push = Push::new(Service::Gotify, &format!("{}/", url), Some("tk")).unwrap();
+        push.notify(&message(BODY), &reminder(CONTENT))
+            .await
+            .unwrap();
+
+        let req = rx.await.unwrap();
+        assert!(req.starts_with("POST /message "));
+        assert!(req.contains("x-gotify-key: tk\r\n"));
+        let (_, body) = req.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(8, body["priority"]);
+        assert_eq!("Scheduled in 30 minutes: Call Bob", body["message"]);
+        assert_eq!(json!(["work", "phone"]), body["extras"]["org::tags"]);
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(3, ntfy_priority(None));
+        assert_eq!(2, ntfy_priority(Some('C')));
+        assert_eq!(5, gotify_priority(None));
+    }
+}
@@ -0,0 +1,34 @@
+use super::Notifier;
+use crate::message::Message;
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use org_parser::Reminder;
+use tracing::info;
+
+/// Prints reminders to stdout, one per line.
+pub struct Stdout;
+
+impl Notifier for Stdout {
+    fn notify<'a>(
+        &'a self,
+        msg: &'a Message,
+        _reminder: &'a Reminder,
+    ) -> BoxFuture<'a, Result<()>> {
+        println!("{}: {}", msg.summary, msg.body);
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Writes reminders to the server log.
+pub struct Log;
+
+impl Notifier for Log {
+    fn notify<'a>(
+        &'a self,
+        msg: &'a Message,
+        _reminder: &'a Reminder,
+    ) -> BoxFuture<'a, Result<()>> {
+        info!("reminder {}: {}", msg.summary, msg.body);
+        async { Ok(()) }.boxed()
+    }
+}
@@ -0,0 +1,74 @@
+use super::{payload, Notifier};
+use crate::message::Message;
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use org_parser::Reminder;
+use reqwest::Client;
+use std::{collections::HashMap, time::Duration};
+
+/// POSTs each reminder as JSON to `url`.
+pub struct Webhook {
+    client: Client,
+    url: String,
+    headers: HashMap<String, String>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, headers: &HashMap<String, String>) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(Webhook {
+            client,
+            url: url.to_string(),
+            headers: headers.clone(),
+        })
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify<'a>(&'a self, msg: &'a Message, reminder: &'a Reminder) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let mut req = self.client.post(&self.url).json(&payload(msg, reminder));
+            for (key, value) in &self.headers {
+                req = req.header(key, value);
+            }
+            req.send().await?.error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{message, mock_server, reminder};
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let (url, rx) = mock_server().await;
+        let headers = HashMap::from([("X-Token".to_string(), "secret".to_string())]);
+        let webhook = Webhook::new(&format!("{}/hook", url), &headers).unwrap();
+        let content = "* TODO Call Bob :work:\nSCHEDULED: <2099-03-04 Wed 10:00>\n";
+        webhook
+            .notify(
+                &message("Scheduled in 30 minutes: Call Bob"),
+                &reminder(content),
+            )
+            .await
+            .unwrap();
+
+        let req = rx.await.unwrap();
+        assert!(req.starts_with("POST /hook "));
+        assert!(req.contains("content-type: application/json\r\n"));
+        assert!(req.contains("x-token: secret\r\n"));
+        let (_, body) = req.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!("Org Reminder", body["summary"]);
+        assert_eq!("Scheduled in 30 minutes: Call Bob", body["body"]);
+        assert_eq!("Call Bob", body["title"]);
+        assert_eq!("scheduled", body["kind"]);
+        assert_eq!("2099-03-04T10:00:00", body["at"]);
+        assert_eq!("2099-03-04T09:30:00", body["datetime"]);
+        assert_eq!(serde_json::json!(["work"]), body["tags"]);
+    }
+}
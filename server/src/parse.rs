@@ -1,41 +1,121 @@
-use anyhow::Result;
-use org_parser::Org;
-use std::path::Path;
+use anyhow::{anyhow, Result};
+use globset::GlobSet;
+use org_parser::Context;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use tokio::{fs::File, task};
 use walkdir::WalkDir;
 
-pub async fn parse_org_file(path: &Path) -> Result<org_parser::Org> {
+/// Emacs lock (`.#foo.org`), auto-save (`#foo.org#`) and backup (`foo.org~`) files.
+pub fn is_ignored(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name.starts_with(".#") || (name.starts_with('#') && name.ends_with('#')) || name.ends_with('~')
+}
+
+/// Decides which files under the `org_path` roots the server indexes.
+///
+/// Globs are matched against the path relative to its root and may match at any depth,
+/// so `*.org_archive` and `archive/**` behave like gitignore patterns.
+#[derive(Debug)]
+pub struct FileFilter {
+    roots: Vec<PathBuf>,
+    include: Vec<GlobSet>,
+    exclude: Vec<GlobSet>,
+}
+
+impl FileFilter {
+    pub fn new(roots: &[String], include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(FileFilter {
+            roots: roots.iter().map(PathBuf::from).collect(),
+            include: build_globs(include)?,
+            exclude: build_globs(exclude)?,
+        })
+    }
+
+    /// Whether `path` is an org file the server should index.
+    pub fn is_org_file(&self, path: &Path) -> bool {
+        if is_ignored(path) {
+            return false;
+        }
+        let rel = self
+            .roots
+            .iter()
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .min_by_key(|rel| rel.components().count())
+            .unwrap_or(path);
+        self.include.iter().any(|g| g.is_match(rel))
+            && !self.exclude.iter().any(|g| g.is_match(rel))
+    }
+
+    /// Org files under `path`, which may be a single file.
+    pub fn org_files<'a>(&'a self, path: &Path) -> impl Iterator<Item = PathBuf> + 'a {
+        WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && self.is_org_file(e.path()))
+            .map(|e| e.into_path())
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<Vec<GlobSet>> {
+    patterns
+        .iter()
+        .map(|p| {
+            globmatch::Builder::new(p)
+                .build_glob_set()
+                .map(|set| set.matcher)
+                .map_err(|err| anyhow!("invalid glob {}", err))
+        })
+        .collect()
+}
+
+pub async fn parse_org_file(path: &Path, ctx: &Context) -> Result<org_parser::Org> {
     let mut file = File::open(path).await?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf).await?;
 
     let content = std::str::from_utf8(&buf)?;
-    let mut ctx = org_parser::Context::new();
+    let mut ctx = ctx.clone();
     let mut org = org_parser::parse(&mut ctx, content)?;
     let p = format!("{}", path.display());
     org.filename = Some(p);
     Ok(org)
 }
 
-pub async fn parse_org_files(path: &str) -> Result<Vec<Org>> {
-    let mut handles = vec![];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path().to_owned();
-        if let Some(ext) = path.extension() {
-            if ext == "org" {
-                let handle = task::spawn(async move { parse_org_file(&path).await });
-                handles.push(handle);
-            }
-        }
+    fn filter(include: &[&str], exclude: &[&str]) -> FileFilter {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        FileFilter::new(&strings(&["/notes"]), &strings(include), &strings(exclude)).unwrap()
     }
 
-    let all_res = futures::future::join_all(handles).await;
+    #[test]
+    fn test_file_filter() {
+        let config: crate::config::Config =
+            toml::from_str("org_path = [\"/notes\"]\nserver_port = 8080").unwrap();
+        let default = config.file_filter().unwrap();
+        assert!(default.is_org_file(Path::new("/notes/todo.org")));
+        assert!(default.is_org_file(Path::new("/notes/work/todo.org")));
+        assert!(!default.is_org_file(Path::new("/notes/todo.org_archive")));
+        assert!(!default.is_org_file(Path::new("/notes/readme.md")));
+        assert!(!default.is_org_file(Path::new("/notes/.#todo.org")));
+
+        let custom = filter(&["*.org", "*.txt"], &[]);
+        assert!(custom.is_org_file(Path::new("/notes/todo.txt")));
+        assert!(custom.is_org_file(Path::new("/notes/work/todo.org")));
+        assert!(!custom.is_org_file(Path::new("/notes/readme.md")));
+
+        // exclusion wins over inclusion, relative to the root
+        let excluded = filter(&["*.org"], &["archive/**", "draft-*.org"]);
+        assert!(excluded.is_org_file(Path::new("/notes/todo.org")));
+        assert!(!excluded.is_org_file(Path::new("/notes/archive/2020.org")));
+        assert!(!excluded.is_org_file(Path::new("/notes/work/draft-plan.org")));
+        assert!(excluded.is_org_file(Path::new("/notes/work/archive.org")));
 
-    let mut orgs = vec![];
-    for org in all_res.into_iter().flatten().flatten() {
-        orgs.push(org);
+        assert!(FileFilter::new(&[], &["[".to_string()], &[]).is_err());
     }
-    Ok(orgs)
 }
@@ -1,47 +1,71 @@
-use crate::{config::Config, notification, parse::parse_org_file};
+use crate::{
+    config::Config,
+    edit,
+    index::Index,
+    live::LiveSender,
+    message::{self, Message, MessageConfig},
+    notification::Notifiers,
+    parse::{parse_org_file, FileFilter},
+    state::State,
+};
 use anyhow::Result;
-use chrono::Local;
-use org_parser::{Org, Reminder};
+use chrono::{Local, NaiveDateTime};
+use org_parser::{Context, Org, Reminder, ReminderOptions};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
     time::{Duration, Instant},
 };
 use tokio::{sync::mpsc, task, time};
 use tracing::{debug, error};
-use walkdir::WalkDir;
 
-async fn scan_reminders(path: &str, tx: mpsc::Sender<Org>) -> Result<()> {
+/// How often the last check time is persisted when nothing was delivered.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+async fn scan_reminders(
+    path: &str,
+    ctx: Context,
+    filter: &FileFilter,
+    index: &Index,
+    tx: mpsc::Sender<OrgEvent>,
+) -> Result<()> {
     let now = Instant::now();
     let mut n = 0;
-    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path().to_owned();
-        if let Some(ext) = path.extension() {
-            if ext == "org" {
-                match parse_org_file(&path).await {
-                    Ok(org) => {
-                        if let Err(err) = tx.send(org).await {
-                            error!("SendError: {:?}", err);
-                        } else {
-                            n += 1;
-                        }
-                    }
-                    Err(err) => {
-                        error!("ParseError: {:?}", err);
-                    }
+    for path in filter.org_files(Path::new(path)) {
+        match parse_org_file(&path, &ctx).await {
+            Ok(org) => {
+                let file = org.filename.clone().unwrap_or_default();
+                index.insert(org);
+                if let Err(err) = tx.send(OrgEvent::Scanned(file)).await {
+                    error!("SendError: {:?}", err);
+                } else {
+                    n += 1;
                 }
             }
+            Err(err) => {
+                error!("ParseError: {:?}", err);
+            }
         }
     }
     debug!("scan: {:?} {} org files {:?}", path, n, now.elapsed());
     Ok(())
 }
 
-pub fn scan(config: &Config, tx: mpsc::Sender<Org>) -> Result<()> {
+pub fn scan(
+    config: &Config,
+    filter: Arc<FileFilter>,
+    index: Arc<Index>,
+    tx: mpsc::Sender<OrgEvent>,
+) -> Result<()> {
     for p in &config.org_path {
         let p = p.clone();
+        let ctx = config.context();
+        let filter = filter.clone();
+        let index = index.clone();
         let tx = tx.clone();
-        let _ = task::spawn(async move {
-            if let Err(err) = scan_reminders(&p, tx).await {
+        task::spawn(async move {
+            if let Err(err) = scan_reminders(&p, ctx, &filter, &index, tx).await {
                 error!("ParseError {:?}", err);
             }
         });
@@ -49,44 +73,299 @@ pub fn scan(config: &Config, tx: mpsc::Sender<Org>) -> Result<()> {
     Ok(())
 }
 
-pub async fn start_check(mut rx: mpsc::Receiver<Org>) -> Result<()> {
-    let _forever = task::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(5));
-        let mut reminders: HashSet<Reminder> = HashSet::new();
+/// Changes to the index and user actions consumed by the reminder checker.
+/// Files are named by path, their documents are read from the index.
+#[derive(Debug)]
+pub enum OrgEvent {
+    /// A file was indexed by the startup scan.
+    Scanned(String),
+    /// A file was (re-)indexed; its reminders replace the previous ones.
+    Updated(String),
+    /// A file or directory was deleted or moved away.
+    Removed(String),
+    /// A file was moved from the first path to the second.
+    Renamed(String, String),
+    /// Remind again after the given delay.
+    Snooze(Reminder, Duration),
+    /// Mark the heading of the reminder done in its file.
+    Done(Reminder),
+}
 
-        loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    // debug!("start check");
-                    let now = Local::now().naive_local();
-                    let mut temp = vec![];
-                    for val in &reminders {
-                        if now > val.datetime {
-                            // notify
-                            let _ = notification::notify("Emacs Org Remainder", &val.title);
-                            debug!("notify : {:?}", val);
-                            temp.push(val.clone());  // remove entry
+fn insert_reminders(pending: &mut HashSet<Reminder>, res: Vec<Reminder>, now: NaiveDateTime) {
+    for r in res {
+        if now < r.datetime {
+            let dr = r.clone();
+            if pending.insert(r) {
+                debug!("append reminder: {:?}", &dr);
+            }
+        }
+    }
+}
+
+fn replace_reminders(
+    reminders: &mut HashMap<String, HashSet<Reminder>>,
+    org: &Org,
+    opts: &ReminderOptions,
+) {
+    let file = org.filename.clone().unwrap_or_default();
+    let now = Local::now().naive_local();
+    let mut pending = HashSet::new();
+    insert_reminders(&mut pending, org.get_reminders(opts), now);
+    debug!("replace reminders: {:?} {}", file, pending.len());
+    reminders.insert(file, pending);
+}
+
+/// Send one catch-up notification per item with reminders that came due after `since`
+/// and were never delivered. Returns whether anything was sent.
+fn notify_missed(
+    org: &Org,
+    since: NaiveDateTime,
+    opts: &ReminderOptions,
+    messages: &MessageConfig,
+    notifiers: &Arc<Notifiers>,
+    state: &mut State,
+) -> bool {
+    let now = Local::now().naive_local();
+    let mut missed: HashMap<(usize, String), Reminder> = HashMap::new();
+    for r in org.get_reminders_after(opts, since) {
+        if r.datetime <= since || now < r.datetime || !state.mark_fired(&r) {
+            continue;
+        }
+        let key = (r.line, r.title.clone());
+        match missed.get(&key) {
+            Some(latest) if r.datetime <= latest.datetime => {}
+            _ => {
+                missed.insert(key, r);
+            }
+        }
+    }
+    for r in missed.values() {
+        let msg = message::format_missed(messages, r);
+        debug!("notify missed : {:?}", r);
+        deliver(notifiers, msg, r.clone());
+    }
+    !missed.is_empty()
+}
+
+/// Hand `msg` to the notifiers without holding up the checker.
+fn deliver(notifiers: &Arc<Notifiers>, msg: Message, reminder: Reminder) {
+    let notifiers = notifiers.clone();
+    task::spawn(async move {
+        notifiers.notify(&msg, &reminder).await;
+    });
+}
+
+fn save_state(state: &mut State, path: &Path) {
+    if let Err(err) = state.save(path) {
+        error!("failed to save state {:?}: {:?}", path, err);
+    }
+}
+
+/// Pending reminders and delivery state, owned by the checker task.
+struct Checker {
+    index: Arc<Index>,
+    ctx: Context,
+    opts: ReminderOptions,
+    messages: MessageConfig,
+    notifiers: Arc<Notifiers>,
+    state: State,
+    state_path: PathBuf,
+    /// Reminders that came due while the server was down are caught up as files are scanned.
+    offline_since: Option<NaiveDateTime>,
+    /// Pending reminders keyed by source file.
+    reminders: HashMap<String, HashSet<Reminder>>,
+    /// Snoozed reminders, kept apart so re-parsing a file doesn't drop them.
+    snoozed: HashSet<Reminder>,
+    saved: Instant,
+}
+
+impl Checker {
+    /// Deliver the reminders that came due before `now`.
+    fn tick(&mut self, now: NaiveDateTime) {
+        let mut notified = false;
+        for pending in self.reminders.values_mut() {
+            let mut temp = vec![];
+            for val in pending.iter() {
+                if now > val.datetime {
+                    if self.state.mark_fired(val) {
+                        // notify
+                        let msg = message::format(&self.messages, val);
+                        debug!("notify : {:?}", val);
+                        deliver(&self.notifiers, msg, val.clone());
+                        notified = true;
+                    }
+                    temp.push(val.clone()); // remove entry
+                }
+            }
+            for val in temp {
+                pending.remove(&val);
+                // schedule the next occurrence of repeated items
+                insert_reminders(pending, val.next_reminders(&self.opts), now);
+            }
+        }
+        let due: Vec<_> = self
+            .snoozed
+            .iter()
+            .filter(|r| now > r.datetime)
+            .cloned()
+            .collect();
+        for val in due {
+            self.snoozed.remove(&val);
+            if self.state.mark_fired(&val) {
+                let msg = message::format(&self.messages, &val);
+                debug!("notify snoozed : {:?}", val);
+                deliver(&self.notifiers, msg, val);
+                notified = true;
+            }
+        }
+        self.state.last_check = Some(now);
+        if notified || self.saved.elapsed() >= SAVE_INTERVAL {
+            save_state(&mut self.state, &self.state_path);
+            self.saved = Instant::now();
+        }
+    }
+
+    /// Drop the snoozed reminders of `file` whose heading no longer asks for them,
+    /// e.g. because it was marked done or deleted.
+    fn purge_snoozed(&mut self, file: &str) {
+        let org = self.index.get(file);
+        let opts = &self.opts;
+        self.snoozed.retain(|r| {
+            if r.file.as_deref() != Some(file) {
+                return true;
+            }
+            let wanted = org.as_ref().is_some_and(|org| {
+                org.get_reminders_after(opts, r.at - chrono::Duration::seconds(1))
+                    .iter()
+                    .any(|c| c.title == r.title && c.at == r.at)
+            });
+            if !wanted {
+                debug!("drop snoozed reminder: {:?}", r);
+            }
+            wanted
+        });
+    }
+
+    fn handle(&mut self, event: OrgEvent) {
+        match event {
+            OrgEvent::Scanned(path) => {
+                let Some(org) = self.index.get(&path) else {
+                    return;
+                };
+                if let Some(since) = self.offline_since {
+                    if notify_missed(
+                        &org,
+                        since,
+                        &self.opts,
+                        &self.messages,
+                        &self.notifiers,
+                        &mut self.state,
+                    ) {
+                        save_state(&mut self.state, &self.state_path);
+                    }
+                }
+                replace_reminders(&mut self.reminders, &org, &self.opts);
+            }
+            OrgEvent::Renamed(from, to) => {
+                // delivered reminders move along, so the catch-up scan doesn't repeat them
+                self.state.rename(&from, &to);
+                save_state(&mut self.state, &self.state_path);
+                if let Some(mut pending) = self.reminders.remove(&from) {
+                    debug!("move reminders: {:?} {:?} {}", from, to, pending.len());
+                    pending = pending
+                        .into_iter()
+                        .map(|mut r| {
+                            r.file = Some(to.clone());
+                            r
+                        })
+                        .collect();
+                    self.reminders.insert(to.clone(), pending);
+                }
+                self.snoozed = self
+                    .snoozed
+                    .drain()
+                    .map(|mut r| {
+                        if r.file.as_deref() == Some(from.as_str()) {
+                            r.file = Some(to.clone());
                         }
+                        r
+                    })
+                    .collect();
+                if let Some(org) = self.index.get(&to) {
+                    replace_reminders(&mut self.reminders, &org, &self.opts);
+                }
+            }
+            OrgEvent::Updated(path) => {
+                if let Some(org) = self.index.get(&path) {
+                    replace_reminders(&mut self.reminders, &org, &self.opts);
+                }
+                self.purge_snoozed(&path);
+            }
+            OrgEvent::Removed(path) => {
+                let dir = format!("{}/", path.trim_end_matches('/'));
+                let removed = |file: &str| file == path || file.starts_with(&dir);
+                self.reminders.retain(|file, pending| {
+                    if removed(file) {
+                        debug!("remove reminders: {:?} {}", file, pending.len());
                     }
-                    for val in temp {
-                        reminders.remove(&val);
+                    !removed(file)
+                });
+                self.snoozed
+                    .retain(|r| !r.file.as_deref().is_some_and(removed));
+            }
+            OrgEvent::Snooze(mut reminder, delay) => {
+                reminder.datetime = Local::now().naive_local()
+                    + chrono::Duration::from_std(delay).unwrap_or_default();
+                debug!("snooze reminder: {:?}", reminder);
+                self.snoozed.insert(reminder);
+            }
+            OrgEvent::Done(reminder) => {
+                self.snoozed.retain(|r| {
+                    r.file != reminder.file || r.title != reminder.title || r.at != reminder.at
+                });
+                let ctx = self.ctx.clone();
+                task::spawn(async move {
+                    if let Err(err) = edit::mark_done(&reminder, &ctx).await {
+                        error!("EditError: {:?}", err);
                     }
+                });
+            }
+        }
+    }
+}
 
+pub async fn start_check(
+    config: &Config,
+    index: Arc<Index>,
+    live: LiveSender,
+    tx: mpsc::Sender<OrgEvent>,
+    mut rx: mpsc::Receiver<OrgEvent>,
+) -> Result<()> {
+    let state_path = config.state_file()?;
+    let state = State::load(&state_path);
+    let mut checker = Checker {
+        index,
+        ctx: config.context(),
+        opts: config.reminder_options()?,
+        messages: config.messages.clone(),
+        notifiers: Arc::new(config.notifiers(tx)?.with_live(live)),
+        offline_since: state.last_check,
+        state,
+        state_path,
+        reminders: HashMap::new(),
+        snoozed: HashSet::new(),
+        saved: Instant::now(),
+    };
+    let _forever = task::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(5));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    checker.tick(Local::now().naive_local());
                 }
                 data = rx.recv() => {
-                    if let Some(org) = data {
-                        let res = org.get_reminders();
-                        if !res.is_empty() {
-                            let now = Local::now().naive_local();
-                            for r in res {
-                                if now < r.datetime {
-                                    let dr = r.clone();
-                                    if reminders.insert(r) {
-                                        debug!("append reminder: {:?}", &dr);
-                                    }
-                                }
-                            }
-                        }
+                    if let Some(event) = data {
+                        checker.handle(event);
                     }
                 }
             }
@@ -94,3 +373,209 @@ pub async fn start_check(mut rx: mpsc::Receiver<Org>) -> Result<()> {
     });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{org, temp_dir};
+
+    fn checker(name: &str, index: Arc<Index>) -> Checker {
+        let (tx, _rx) = mpsc::channel(1);
+        Checker {
+            index,
+            ctx: Context::new(),
+            opts: ReminderOptions::default(),
+            messages: MessageConfig::default(),
+            notifiers: Arc::new(Notifiers::new(&[], tx).unwrap()),
+            state: State::default(),
+            state_path: temp_dir(name).join("state.bin"),
+            offline_since: None,
+            reminders: HashMap::new(),
+            snoozed: HashSet::new(),
+            saved: Instant::now(),
+        }
+    }
+
+    fn titles(checker: &Checker, file: &str) -> Vec<String> {
+        let mut titles: Vec<_> = checker.reminders[file]
+            .iter()
+            .map(|r| r.title.clone())
+            .collect();
+        titles.sort();
+        titles.dedup();
+        titles
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let index = Arc::new(Index::new());
+        let mut checker = checker("checker-update", index.clone());
+        index.insert(org(
+            "/notes/a.org",
+            "* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n* Meeting\nSCHEDULED: <2099-03-05 Thu 10:00>\n",
+        ));
+        index.insert(org(
+            "/notes/sub/b.org",
+            "* Dentist\nSCHEDULED: <2099-03-06 Fri 10:00>\n",
+        ));
+        checker.handle(OrgEvent::Scanned("/notes/a.org".to_string()));
+        checker.handle(OrgEvent::Scanned("/notes/sub/b.org".to_string()));
+        assert_eq!(
+            vec!["Call Bob", "Meeting"],
+            titles(&checker, "/notes/a.org")
+        );
+        assert_eq!(vec!["Dentist"], titles(&checker, "/notes/sub/b.org"));
+
+        // the edited file replaces its reminders, a removed heading is retracted
+        index.insert(org(
+            "/notes/a.org",
+            "* Call Alice\nSCHEDULED: <2099-03-04 Wed 11:00>\n",
+        ));
+        checker.handle(OrgEvent::Updated("/notes/a.org".to_string()));
+        assert_eq!(vec!["Call Alice"], titles(&checker, "/notes/a.org"));
+        assert!(checker.reminders["/notes/a.org"]
+            .iter()
+            .all(|r| r.at.format("%R").to_string() == "11:00"));
+        assert_eq!(vec!["Dentist"], titles(&checker, "/notes/sub/b.org"));
+
+        // removing a directory retracts the reminders of every file below it
+        checker.handle(OrgEvent::Removed("/notes/sub".to_string()));
+        assert!(!checker.reminders.contains_key("/notes/sub/b.org"));
+        assert!(checker.reminders.contains_key("/notes/a.org"));
+
+        checker.handle(OrgEvent::Removed("/notes/a.org".to_string()));
+        assert!(checker.reminders.is_empty());
+    }
+
+    #[test]
+    fn test_rename() {
+        let index = Arc::new(Index::new());
+        let mut checker = checker("checker-rename", index.clone());
+        let content = "* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n";
+        index.insert(org("/notes/a.org", content));
+        checker.handle(OrgEvent::Scanned("/notes/a.org".to_string()));
+        let fired = checker.reminders["/notes/a.org"]
+            .iter()
+            .next()
+            .unwrap()
+            .clone();
+        assert!(checker.state.mark_fired(&fired));
+
+        index.remove("/notes/a.org");
+        index.insert(org("/notes/b.org", content));
+        checker.handle(OrgEvent::Renamed(
+            "/notes/a.org".to_string(),
+            "/notes/b.org".to_string(),
+        ));
+        assert!(!checker.reminders.contains_key("/notes/a.org"));
+        assert_eq!(vec!["Call Bob"], titles(&checker, "/notes/b.org"));
+
+        // what was delivered from the old path counts as delivered from the new one
+        let moved = Reminder {
+            file: Some("/notes/b.org".to_string()),
+            ..fired
+        };
+        assert!(!checker.state.mark_fired(&moved));
+    }
+
+    #[tokio::test]
+    async fn test_catch_up() {
+        let index = Arc::new(Index::new());
+        let mut checker = checker("checker-catch-up", index.clone());
+        let now = Local::now().naive_local();
+        checker.offline_since = Some(now - chrono::Duration::hours(2));
+        checker.opts.offsets = vec![Duration::ZERO];
+        let stamp = |d: chrono::Duration| (now + d).format("<%F %a %H:%M>").to_string();
+        let content = format!(
+            "* Before\nSCHEDULED: {}\n* Missed\nSCHEDULED: {}\n* Later\nSCHEDULED: {}\n",
+            stamp(chrono::Duration::hours(-3)),
+            stamp(chrono::Duration::hours(-1)),
+            stamp(chrono::Duration::hours(1)),
+        );
+        index.insert(org("/notes/a.org", &content));
+        checker.handle(OrgEvent::Scanned("/notes/a.org".to_string()));
+
+        // only what came due while the server was down is delivered, and recorded as such
+        let mut missed = vec![];
+        for r in index
+            .get("/notes/a.org")
+            .unwrap()
+            .get_reminders_after(&checker.opts, now - chrono::Duration::hours(4))
+        {
+            if !checker.state.mark_fired(&r) {
+                missed.push(r.title);
+            }
+        }
+        assert_eq!(vec!["Missed"], missed);
+        assert_eq!(vec!["Later"], titles(&checker, "/notes/a.org"));
+    }
+
+    #[tokio::test]
+    async fn test_purge_snoozed() {
+        let index = Arc::new(Index::new());
+        let mut checker = checker("checker-snooze", index.clone());
+        checker.opts.skip_done = true;
+        let content = "* TODO Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n* TODO Meeting\nSCHEDULED: <2099-03-05 Thu 10:00>\n";
+        index.insert(org("/notes/a.org", content));
+        index.insert(org("/notes/b.org", content));
+        checker.handle(OrgEvent::Scanned("/notes/a.org".to_string()));
+        checker.handle(OrgEvent::Scanned("/notes/b.org".to_string()));
+        let snooze = |checker: &mut Checker, file: &str, title: &str| {
+            let r = checker.reminders[file]
+                .iter()
+                .find(|r| r.title == title)
+                .unwrap()
+                .clone();
+            checker.handle(OrgEvent::Snooze(r, Duration::from_secs(300)));
+        };
+        snooze(&mut checker, "/notes/a.org", "Call Bob");
+        snooze(&mut checker, "/notes/a.org", "Meeting");
+        snooze(&mut checker, "/notes/b.org", "Call Bob");
+        assert_eq!(3, checker.snoozed.len());
+
+        // a heading marked done no longer reminds, the others stay snoozed
+        index.insert(org(
+            "/notes/a.org",
+            "* DONE Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n* TODO Meeting\nSCHEDULED: <2099-03-05 Thu 10:00>\n",
+        ));
+        checker.handle(OrgEvent::Updated("/notes/a.org".to_string()));
+        let mut snoozed: Vec<_> = checker
+            .snoozed
+            .iter()
+            .map(|r| (r.file.clone().unwrap(), r.title.clone()))
+            .collect();
+        snoozed.sort();
+        assert_eq!(
+            vec![
+                ("/notes/a.org".to_string(), "Meeting".to_string()),
+                ("/notes/b.org".to_string(), "Call Bob".to_string()),
+            ],
+            snoozed
+        );
+
+        let done = checker
+            .snoozed
+            .iter()
+            .find(|r| r.title == "Meeting")
+            .unwrap()
+            .clone();
+        checker.handle(OrgEvent::Done(Reminder {
+            datetime: done.at,
+            ..done
+        }));
+        assert_eq!(1, checker.snoozed.len());
+
+        // a snooze follows its file when it is renamed
+        index.remove("/notes/b.org");
+        index.insert(org("/notes/c.org", content));
+        checker.handle(OrgEvent::Renamed(
+            "/notes/b.org".to_string(),
+            "/notes/c.org".to_string(),
+        ));
+        let moved: Vec<_> = checker.snoozed.iter().map(|r| r.file.clone()).collect();
+        assert_eq!(vec![Some("/notes/c.org".to_string())], moved);
+
+        checker.handle(OrgEvent::Removed("/notes/c.org".to_string()));
+        assert!(checker.snoozed.is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+use org_parser::{Org, Section};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// How much a term counts depending on where it appears.
+const TITLE_WEIGHT: f32 = 3.0;
+const TAG_WEIGHT: f32 = 2.0;
+const PROPERTY_WEIGHT: f32 = 1.5;
+const BODY_WEIGHT: f32 = 1.0;
+
+/// BM25 parameters.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Characters of context around the first match in a snippet.
+const SNIPPET_BEFORE: usize = 30;
+const SNIPPET_CHARS: usize = 120;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub file: String,
+    /// Titles from the top-level heading down to the matching one.
+    pub path: Vec<String>,
+    /// Line of the first match in the body, or of the heading for matches in its
+    /// title, tags or properties.
+    pub line: usize,
+    pub snippet: String,
+    pub score: f32,
+}
+
+/// A heading, the unit search results point at.
+struct Entry {
+    file: String,
+    path: Vec<String>,
+    line: usize,
+    /// Body text with whitespace collapsed, for snippets.
+    body: String,
+    /// Where each source line starts in `body`, in chars, and its line number.
+    body_lines: Vec<(usize, usize)>,
+    /// Number of terms in the heading.
+    len: usize,
+    /// Distinct terms of the heading, to find its postings on removal.
+    terms: Vec<String>,
+}
+
+/// Inverted index over the headings of all indexed files, updated file by file.
+#[derive(Default)]
+pub struct SearchIndex {
+    entries: HashMap<u64, Entry>,
+    files: HashMap<String, Vec<u64>>,
+    /// Weighted term frequency per entry.
+    terms: HashMap<String, HashMap<u64, f32>>,
+    total_len: usize,
+    next: u64,
+}
+
+impl SearchIndex {
+    /// Index the headings of `org`, replacing the ones previously indexed for its file.
+    pub fn insert(&mut self, org: &Org) {
+        let file = org.filename.clone().unwrap_or_default();
+        self.remove(&file);
+        let mut ids = vec![];
+        let mut path = vec![];
+        for sec in &org.sections {
+            self.insert_section(&file, sec, &mut path, &mut ids);
+        }
+        self.files.insert(file, ids);
+    }
+
+    fn insert_section(
+        &mut self,
+        file: &str,
+        sec: &Section,
+        path: &mut Vec<String>,
+        ids: &mut Vec<u64>,
+    ) {
+        path.push(sec.title.clone());
+
+        let mut body = String::new();
+        let mut body_lines = vec![];
+        let mut chars = 0;
+        for content in &sec.contents {
+            for (line, text) in (content.line..).zip(content.contents.lines()) {
+                for word in text.split_whitespace() {
+                    if !body.is_empty() {
+                        body.push(' ');
+                        chars += 1;
+                    }
+                    if body_lines.last().map(|&(_, l)| l) != Some(line) {
+                        body_lines.push((chars, line));
+                    }
+                    body.push_str(word);
+                    chars += word.chars().count();
+                }
+            }
+        }
+        let mut tf: HashMap<String, f32> = HashMap::new();
+        let mut len = 0;
+        let mut add = |text: &str, weight: f32| {
+            for term in index_terms(text) {
+                *tf.entry(term).or_default() += weight;
+                len += 1;
+            }
+        };
+        add(&sec.title, TITLE_WEIGHT);
+        for tag in &sec.tags {
+            add(tag, TAG_WEIGHT);
+        }
+        for prop in sec.properties.iter().flat_map(|p| p.children.iter()) {
+            add(&prop.value, PROPERTY_WEIGHT);
+        }
+        add(&body, BODY_WEIGHT);
+
+        let id = self.next;
+        self.next += 1;
+        let terms: Vec<String> = tf.keys().cloned().collect();
+        for (term, freq) in tf {
+            self.terms.entry(term).or_default().insert(id, freq);
+        }
+        self.total_len += len;
+        self.entries.insert(
+            id,
+            Entry {
+                file: file.to_string(),
+                path: path.clone(),
+                line: sec.line,
+                body,
+                body_lines,
+                len,
+                terms,
+            },
+        );
+        ids.push(id);
+
+        for child in &sec.sections {
+            self.insert_section(file, child, path, ids);
+        }
+        path.pop();
+    }
+
+    /// Drop the headings of `file`.
+    pub fn remove(&mut self, file: &str) {
+        let Some(ids) = self.files.remove(file) else {
+            return;
+        };
+        for id in ids {
+            let Some(entry) = self.entries.remove(&id) else {
+                continue;
+            };
+            self.total_len -= entry.len;
+            for term in entry.terms {
+                if let Some(postings) = self.terms.get_mut(&term) {
+                    postings.remove(&id);
+                    if postings.is_empty() {
+                        self.terms.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Headings containing every term of `query`, best match first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        let postings: Option<Vec<&HashMap<u64, f32>>> =
+            terms.iter().map(|t| self.terms.get(t)).collect();
+        let Some(mut postings) = postings.filter(|p| !p.is_empty()) else {
+            return vec![];
+        };
+        postings.sort_by_key(|p| p.len());
+
+        let n = self.entries.len() as f32;
+        let avg_len = self.total_len as f32 / n;
+        let mut hits: Vec<SearchHit> = postings[0]
+            .keys()
+            .filter(|id| postings[1..].iter().all(|p| p.contains_key(id)))
+            .filter_map(|id| {
+                let entry = self.entries.get(id)?;
+                let norm = 1.0 - B + B * entry.len as f32 / avg_len;
+                let score = postings
+                    .iter()
+                    .map(|p| {
+                        let tf = p[id];
+                        let df = p.len() as f32;
+                        let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                        idf * tf * (K1 + 1.0) / (tf + K1 * norm)
+                    })
+                    .sum();
+                Some(SearchHit {
+                    file: entry.file.clone(),
+                    path: entry.path.clone(),
+                    line: hit_line(entry, &terms),
+                    snippet: snippet(entry, &terms),
+                    score,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.file.cmp(&b.file))
+                .then(a.line.cmp(&b.line))
+        });
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// Char position of the first of `terms` in `text`, ignoring case.
+fn first_match(text: &str, terms: &[String]) -> Option<usize> {
+    // one lowercase char per char so positions line up with the chars of `text`
+    let lower: Vec<char> = text
+        .chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect();
+    terms
+        .iter()
+        .filter_map(|term| {
+            let term: Vec<char> = term.chars().collect();
+            lower.windows(term.len()).position(|w| w == term.as_slice())
+        })
+        .min()
+}
+
+/// Line of the first match in the body, falling back to the heading's line.
+fn hit_line(entry: &Entry, terms: &[String]) -> usize {
+    first_match(&entry.body, terms)
+        .and_then(|pos| {
+            entry
+                .body_lines
+                .iter()
+                .rev()
+                .find(|(start, _)| *start <= pos)
+        })
+        .map_or(entry.line, |&(_, line)| line)
+}
+
+/// Part of the body around the first matching term, or its beginning.
+fn snippet(entry: &Entry, terms: &[String]) -> String {
+    let text = if entry.body.is_empty() {
+        entry.path.last().map(|s| s.as_str()).unwrap_or_default()
+    } else {
+        &entry.body
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let start = first_match(text, terms)
+        .map(|pos| pos.saturating_sub(SNIPPET_BEFORE))
+        .unwrap_or(0);
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+
+    let mut res = String::new();
+    if start > 0 {
+        res.push('…');
+    }
+    res.extend(&chars[start..end]);
+    if end < chars.len() {
+        res.push('…');
+    }
+    res
+}
+
+/// Scripts written without spaces between words, indexed as character bigrams and unigrams.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{ff66}'..='\u{ff9f}'
+        | '\u{ac00}'..='\u{d7af}')
+}
+
+/// Lowercase words, with CJK runs split into overlapping bigrams.
+pub fn tokenize(text: &str) -> Vec<String> {
+    split_terms(text, false)
+}
+
+/// Terms indexed for `text`: its tokens plus every CJK character on its own, so
+/// single-character queries also match inside longer runs.
+fn index_terms(text: &str) -> Vec<String> {
+    split_terms(text, true)
+}
+
+fn split_terms(text: &str, unigrams: bool) -> Vec<String> {
+    let flush_cjk = |run: &mut Vec<char>, tokens: &mut Vec<String>| {
+        if run.len() == 1 || unigrams {
+            tokens.extend(run.iter().map(|c| c.to_string()));
+        }
+        if run.len() > 1 {
+            tokens.extend(run.windows(2).map(|w| w.iter().collect::<String>()));
+        }
+        run.clear();
+    };
+
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut cjk = vec![];
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            cjk.push(c);
+        } else {
+            if !cjk.is_empty() {
+                flush_cjk(&mut cjk, &mut tokens);
+            }
+            if c.is_alphanumeric() {
+                word.extend(c.to_lowercase());
+            } else if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    if !cjk.is_empty() {
+        flush_cjk(&mut cjk, &mut tokens);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::org;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec!["call", "bob", "at", "10"],
+            tokenize("Call Bob, at 10!")
+        );
+        assert_eq!(vec!["会議", "議室", "room"], tokenize("会議室 room"));
+        assert_eq!(vec!["本"], tokenize("本"));
+        assert_eq!(
+            vec!["会", "議", "室", "会議", "議室", "room"],
+            index_terms("会議室 room")
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::default();
+        index.insert(&org(
+            "/notes/a.org",
+            "* Projects\n** Garden :home:\nPlant tomatoes and water the garden every morning.\n* Shopping\nBuy tomatoes.\n* Tomatoes\nA sauce recipe.\n",
+        ));
+        index.insert(&org("/notes/b.org", "* 会議の準備\n資料を作る\n"));
+
+        // title matches outrank body matches
+        let hits = index.search("tomatoes", 10);
+        assert_eq!(3, hits.len());
+        assert_eq!(vec!["Tomatoes"], hits[0].path);
+        let hits = index.search("garden TOMATOES", 10);
+        assert_eq!(1, hits.len());
+        assert_eq!(vec!["Projects", "Garden"], hits[0].path);
+        assert_eq!(3, hits[0].line);
+        assert!(hits[0].snippet.starts_with("Plant tomatoes"));
+
+        let hits = index.search("home", 10);
+        assert_eq!("Garden", hits[0].path[1]);
+        // a match in the tags points at the heading
+        assert_eq!(2, hits[0].line);
+
+        index.insert(&org(
+            "/notes/c.org",
+            "* Zoo
+First line.
+
+The zebra, on the fourth line.
+zebra again
+",
+        ));
+        let hits = index.search("zebra", 10);
+        assert_eq!(4, hits[0].line);
+        assert_eq!(1, index.search("zoo", 10)[0].line);
+        index.remove("/notes/c.org");
+
+        assert_eq!("/notes/b.org", index.search("会議", 10)[0].file);
+        // a single character matches inside a longer run
+        assert_eq!("/notes/b.org", index.search("議", 10)[0].file);
+        assert_eq!(1, index.search("資", 10).len());
+        assert!(index.search("nothing", 10).is_empty());
+        assert!(index.search("", 10).is_empty());
+
+        // re-indexing a file replaces its headings
+        index.insert(&org("/notes/a.org", "* Shopping\nBuy milk.\n"));
+        assert!(index.search("tomatoes", 10).is_empty());
+        assert_eq!(1, index.search("milk", 10).len());
+        index.remove("/notes/a.org");
+        assert!(index.search("milk", 10).is_empty());
+        assert_eq!(1, index.search("資料", 10).len());
+        // postings of removed headings are dropped with them
+        index.remove("/notes/b.org");
+        assert!(index.terms.is_empty());
+        assert_eq!(0, index.total_len);
+    }
+}
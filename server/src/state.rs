@@ -0,0 +1,161 @@
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+use org_parser::Reminder;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path};
+use tracing::error;
+
+/// How long delivered reminders are remembered after the last check, so clock or
+/// time zone changes can't deliver them twice.
+const RETENTION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Fired {
+    file: Option<String>,
+    title: String,
+    datetime: NaiveDateTime,
+}
+
+impl From<&Reminder> for Fired {
+    fn from(r: &Reminder) -> Self {
+        Fired {
+            file: r.file.clone(),
+            title: r.title.clone(),
+            datetime: r.datetime,
+        }
+    }
+}
+
+/// Reminder delivery state kept across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Last time the checker looked for due reminders.
+    pub last_check: Option<NaiveDateTime>,
+    fired: HashSet<Fired>,
+}
+
+impl State {
+    /// Load the state from `path`, starting afresh if it is missing or unreadable.
+    pub fn load(path: &Path) -> State {
+        let buf = match fs::read(path) {
+            Ok(buf) => buf,
+            Err(_) => return State::default(),
+        };
+        match bincode::deserialize(&buf) {
+            Ok(state) => state,
+            Err(err) => {
+                error!("ignore broken state file {:?}: {:?}", path, err);
+                State::default()
+            }
+        }
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        if let Some(last_check) = self.last_check {
+            let limit = last_check - Duration::days(RETENTION_DAYS);
+            self.fired.retain(|f| f.datetime > limit);
+        }
+        let buf = bincode::serialize(self)?;
+        // replace the file atomically so a crash can't leave half a state behind
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Carry the delivery records of file `from` over to the path it was moved to.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        self.fired = self
+            .fired
+            .drain()
+            .map(|mut f| {
+                if f.file.as_deref() == Some(from) {
+                    f.file = Some(to.to_string());
+                }
+                f
+            })
+            .collect();
+    }
+
+    /// Record `reminder` as delivered, returning false if it already was.
+    pub fn mark_fired(&mut self, reminder: &Reminder) -> bool {
+        self.fired.insert(Fired::from(reminder))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{org, temp_dir};
+    use org_parser::ReminderOptions;
+
+    fn reminder(datetime: &str) -> Reminder {
+        let org = org(
+            "/notes/a.org",
+            "* Call Bob\nSCHEDULED: <2099-03-04 Wed 10:00>\n",
+        );
+        let mut r = org.get_reminders(&ReminderOptions::default()).remove(0);
+        r.datetime = datetime.parse().unwrap();
+        r
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir("state-save");
+        let path = dir.join("state.bin");
+        assert!(State::load(&path).last_check.is_none());
+
+        let mut state = State {
+            last_check: Some("2099-03-10T12:00:00".parse().unwrap()),
+            ..State::default()
+        };
+        assert!(state.mark_fired(&reminder("2099-03-10T10:00:00")));
+        assert!(!state.mark_fired(&reminder("2099-03-10T10:00:00")));
+        state.save(&path).unwrap();
+        // written in place of the old file, without leaving the temporary one behind
+        state.save(&path).unwrap();
+        assert_eq!(vec![path.clone()], files(&dir));
+
+        let mut loaded = State::load(&path);
+        assert_eq!(state.last_check, loaded.last_check);
+        assert!(!loaded.mark_fired(&reminder("2099-03-10T10:00:00")));
+        assert!(loaded.mark_fired(&reminder("2099-03-10T11:00:00")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = temp_dir("state-prune");
+        let path = dir.join("state.bin");
+        let mut state = State {
+            last_check: Some("2099-03-10T12:00:00".parse().unwrap()),
+            ..State::default()
+        };
+        state.mark_fired(&reminder("2099-03-03T11:00:00"));
+        state.mark_fired(&reminder("2099-03-03T13:00:00"));
+        state.save(&path).unwrap();
+
+        let mut loaded = State::load(&path);
+        assert!(loaded.mark_fired(&reminder("2099-03-03T11:00:00")));
+        assert!(!loaded.mark_fired(&reminder("2099-03-03T13:00:00")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_broken() {
+        let dir = temp_dir("state-broken");
+        let path = dir.join("state.bin");
+        fs::write(&path, b"\xff\xff\xff").unwrap();
+        let state = State::load(&path);
+        assert!(state.last_check.is_none());
+        assert!(state.fired.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn files(dir: &Path) -> Vec<std::path::PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect()
+    }
+}
@@ -9,3 +9,9 @@ pub fn get_config_file(name: &str) -> Result<PathBuf> {
         xdg::BaseDirectories::with_prefix(APP_NAME).context("failed get xdg directory")?;
     xdg_dir.place_config_file(name).context("failed get path")
 }
+
+pub fn get_state_file(name: &str) -> Result<PathBuf> {
+    let xdg_dir =
+        xdg::BaseDirectories::with_prefix(APP_NAME).context("failed get xdg directory")?;
+    xdg_dir.place_state_file(name).context("failed get path")
+}
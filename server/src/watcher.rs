@@ -1,21 +1,59 @@
-use crate::{config::Config, parse::parse_org_file};
+use crate::{
+    config::Config,
+    index::Index,
+    live::{LiveEvent, LiveSender},
+    parse::{is_ignored, parse_org_file, FileFilter},
+    reminders::OrgEvent,
+};
 use anyhow::Result;
-use notify::event::EventKind;
+use notify::event::{EventKind, ModifyKind, RenameMode};
 use notify::{RecommendedWatcher, Watcher};
-use org_parser::Org;
+use org_parser::Context;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Builder;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task;
+use tokio::time::{self, Instant};
 use tracing::{debug, error};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Change {
+    Created,
+    Modified,
+    /// Moved here from another path.
+    Renamed(PathBuf),
+}
+
 pub struct OrgWatcher {
-    org_sender: Sender<Org>,
+    org_sender: Sender<OrgEvent>,
+    ctx: Context,
+    filter: Arc<FileFilter>,
+    index: Arc<Index>,
+    live: LiveSender,
+    debounce: Duration,
 }
 
 //
 impl OrgWatcher {
-    pub fn new(org_sender: Sender<Org>) -> Self {
-        OrgWatcher { org_sender }
+    pub fn new(
+        org_sender: Sender<OrgEvent>,
+        ctx: Context,
+        filter: Arc<FileFilter>,
+        index: Arc<Index>,
+        live: LiveSender,
+        debounce: Duration,
+    ) -> Self {
+        OrgWatcher {
+            org_sender,
+            ctx,
+            filter,
+            index,
+            live,
+            debounce,
+        }
     }
 
     fn create_watcher(
@@ -40,7 +78,7 @@ impl OrgWatcher {
     }
 
     async fn watch_file(self, paths: Vec<String>) -> notify::Result<()> {
-        let (mut watcher, mut rx) = self.create_watcher()?;
+        let (mut watcher, rx) = self.create_watcher()?;
         debug!("create watcher");
 
         for path in paths {
@@ -48,66 +86,356 @@ impl OrgWatcher {
             debug!("start watch file: {:?}", path);
         }
 
-        let mut prev_event = None;
+        self.handle_events(rx).await;
+        Ok(())
+    }
+
+    /// Coalesce events per path and handle each path once it is quiet for `debounce`.
+    async fn handle_events(&self, mut rx: Receiver<notify::Result<notify::Event>>) {
+        let mut pending: HashMap<PathBuf, (Instant, Change)> = HashMap::new();
         loop {
-            let res = rx.recv().await;
-            let Some(res) = res else {
-                break;
+            let next = pending.values().map(|(deadline, _)| *deadline).min();
+            let flush = async {
+                match next {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
             };
 
-            match res {
-                Ok(event) => {
-                    if let Some(old_event) = prev_event {
-                        if old_event == event {
-                            // same event skip
-                            prev_event = Some(event.clone());
-                            continue;
+            tokio::select! {
+                res = rx.recv() => {
+                    let Some(res) = res else {
+                        break;
+                    };
+                    match res {
+                        Ok(event) => {
+                            self.record(&mut pending, &event);
+                        }
+                        Err(e) => {
+                            error!("Error watching file: {:?}", e);
                         }
                     }
-                    prev_event = Some(event.clone());
-                    self.notify(&event).await;
                 }
-                Err(e) => {
-                    error!("Error watching file: {:?}", e);
+                _ = flush => {
+                    let now = Instant::now();
+                    let ready: Vec<_> = pending
+                        .iter()
+                        .filter(|(_, (deadline, _))| *deadline <= now)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+                    for path in ready {
+                        if let Some((_, change)) = pending.remove(&path) {
+                            self.apply(&path, change).await;
+                        }
+                    }
                 }
             }
         }
+    }
 
-        Ok(())
+    fn record(&self, pending: &mut HashMap<PathBuf, (Instant, Change)>, event: &notify::Event) {
+        let change = match event.kind {
+            EventKind::Create(_) => Change::Created,
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let [from, to] = &event.paths[..] {
+                    self.touch(pending, from, Change::Modified);
+                    self.touch(pending, to, Change::Renamed(from.clone()));
+                }
+                return;
+            }
+            EventKind::Modify(ModifyKind::Name(_)) => Change::Created,
+            EventKind::Modify(_) | EventKind::Remove(_) => Change::Modified,
+            _ => {
+                // debug!("{:?}", event);
+                return;
+            }
+        };
+        for p in &event.paths {
+            self.touch(pending, p, change.clone());
+        }
     }
 
-    async fn notify(&self, event: &notify::Event) {
-        match event.kind {
-            EventKind::Create(_) => {
-                //
+    fn touch(
+        &self,
+        pending: &mut HashMap<PathBuf, (Instant, Change)>,
+        path: &Path,
+        change: Change,
+    ) {
+        if is_ignored(path) {
+            return;
+        }
+        let deadline = Instant::now() + self.debounce;
+        pending
+            .entry(path.to_path_buf())
+            .and_modify(|(d, c)| {
+                *d = deadline;
+                // a plain modification doesn't hide how the path came to be
+                if change != Change::Modified {
+                    *c = change.clone();
+                }
+            })
+            .or_insert((deadline, change));
+    }
+
+    /// Bring the index in line with the current state of `path`.
+    async fn apply(&self, path: &Path, change: Change) {
+        if !path.exists() {
+            // a deleted directory can't be told from a file any more, so drop whatever
+            // was indexed at or below the path
+            self.remove(path).await;
+            return;
+        }
+        match change {
+            Change::Renamed(from) if self.filter.is_org_file(path) => {
+                self.rename(&from, path).await;
             }
-            EventKind::Modify(_data) => {
-                for p in &event.paths {
-                    match parse_org_file(p).await {
-                        Ok(org) => {
-                            if let Err(err) = self.org_sender.send(org).await {
-                                error!("SendError: {:?}", err);
-                            }
-                        }
-                        Err(err) => {
-                            error!("ParseError: {:?}", err);
-                        }
+            Change::Renamed(from) if path.is_dir() => {
+                for p in self.filter.org_files(path) {
+                    if let Ok(rel) = p.strip_prefix(path) {
+                        self.rename(&from.join(rel), &p).await;
+                    }
+                }
+            }
+            Change::Created if path.is_dir() => {
+                for p in self.filter.org_files(path) {
+                    if let Some(file) = self.index_file(&p).await {
+                        self.send(OrgEvent::Updated(file)).await;
                     }
                 }
             }
             _ => {
-                // debug!("{:?}", event);
+                if self.filter.is_org_file(path) {
+                    if let Some(file) = self.index_file(path).await {
+                        self.send(OrgEvent::Updated(file)).await;
+                    }
+                }
             }
         }
     }
+
+    async fn rename(&self, from: &Path, to: &Path) {
+        let from = format!("{}", from.display());
+        self.unindex(&from);
+        if let Some(to) = self.index_file(to).await {
+            self.send(OrgEvent::Renamed(from, to)).await;
+        }
+    }
+
+    async fn send(&self, event: OrgEvent) {
+        if let Err(err) = self.org_sender.send(event).await {
+            error!("SendError: {:?}", err);
+        }
+    }
+
+    /// Parse `path` into the index, returning its file name there.
+    async fn index_file(&self, path: &Path) -> Option<String> {
+        match parse_org_file(path, &self.ctx).await {
+            Ok(org) => {
+                let file = org.filename.clone().unwrap_or_default();
+                self.index.insert(org);
+                let _ = self
+                    .live
+                    .send(LiveEvent::DocumentChanged { file: file.clone() });
+                Some(file)
+            }
+            Err(err) => {
+                error!("ParseError: {:?}", err);
+                None
+            }
+        }
+    }
+
+    async fn remove(&self, path: &Path) {
+        let path = format!("{}", path.display());
+        if self.unindex(&path) {
+            self.send(OrgEvent::Removed(path)).await;
+        }
+    }
+
+    /// Drop `path` from the index, telling web clients about each removed file.
+    /// Returns whether anything was indexed there.
+    fn unindex(&self, path: &str) -> bool {
+        let removed = self.index.remove(path);
+        let found = !removed.is_empty();
+        for file in removed {
+            let _ = self.live.send(LiveEvent::DocumentRemoved { file });
+        }
+        found
+    }
 }
 
-pub fn watch_files(config: &Config, tx: Sender<Org>) -> Result<()> {
+pub fn watch_files(
+    config: &Config,
+    filter: Arc<FileFilter>,
+    index: Arc<Index>,
+    live: LiveSender,
+    tx: Sender<OrgEvent>,
+) -> Result<()> {
     let paths = config.org_path.clone();
+    let ctx = config.context();
+    let debounce = Duration::from_millis(config.watch_debounce_ms);
     let _forever = task::spawn(async move {
-        let watcher = OrgWatcher::new(tx);
+        let watcher = OrgWatcher::new(tx, ctx, filter, index, live, debounce);
         let _ = watcher.watch_file(paths).await;
     });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::temp_dir, live};
+    use notify::event::{CreateKind, DataChange};
+    use std::fs;
+    use tokio::sync::mpsc;
+
+    fn watcher(root: &Path) -> (OrgWatcher, Receiver<OrgEvent>) {
+        watcher_with_debounce(root, Duration::ZERO)
+    }
+
+    fn watcher_with_debounce(root: &Path, debounce: Duration) -> (OrgWatcher, Receiver<OrgEvent>) {
+        let (tx, rx) = mpsc::channel(16);
+        let root = format!("{}", root.display());
+        let filter = FileFilter::new(&[root], &["*.org".to_string()], &[]).unwrap();
+        let watcher = OrgWatcher::new(
+            tx,
+            Context::new(),
+            Arc::new(filter),
+            Arc::new(Index::new()),
+            live::channel(),
+            debounce,
+        );
+        (watcher, rx)
+    }
+
+    fn name(path: &Path) -> String {
+        format!("{}", path.display())
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let root = temp_dir("watch-rename");
+        let (watcher, mut rx) = watcher(&root);
+        let a = root.join("a.org");
+        let b = root.join("b.org");
+        fs::write(&a, "* Task\nSCHEDULED: <2099-03-04 Wed 10:00>\n").unwrap();
+        watcher.apply(&a, Change::Created).await;
+        assert!(matches!(rx.recv().await, Some(OrgEvent::Updated(f)) if f == name(&a)));
+
+        fs::rename(&a, &b).unwrap();
+        watcher.apply(&a, Change::Modified).await;
+        watcher.apply(&b, Change::Renamed(a.clone())).await;
+        match rx.recv().await {
+            Some(OrgEvent::Removed(f)) => assert_eq!(name(&a), f),
+            other => panic!("unexpected {:?}", other),
+        }
+        match rx.recv().await {
+            Some(OrgEvent::Renamed(from, to)) => {
+                assert_eq!(name(&a), from);
+                assert_eq!(name(&b), to);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(watcher.index.get(&name(&a)).is_none());
+        assert!(watcher.index.get(&name(&b)).is_some());
+
+        // files in a renamed directory are renamed one by one
+        let dir = root.join("old.d");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("c.org"), "* Other\n").unwrap();
+        watcher.apply(&dir, Change::Created).await;
+        assert!(matches!(rx.recv().await, Some(OrgEvent::Updated(_))));
+        let moved = root.join("new.d");
+        fs::rename(&dir, &moved).unwrap();
+        watcher.apply(&moved, Change::Renamed(dir.clone())).await;
+        match rx.recv().await {
+            Some(OrgEvent::Renamed(from, to)) => {
+                assert_eq!(name(&dir.join("c.org")), from);
+                assert_eq!(name(&moved.join("c.org")), to);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir() {
+        let root = temp_dir("watch-remove");
+        let (watcher, mut rx) = watcher(&root);
+        let dir = root.join("notes.d");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.org"), "* A\n").unwrap();
+        fs::write(dir.join("sub/b.org"), "* B\n").unwrap();
+        fs::write(root.join("c.org"), "* C\n").unwrap();
+        watcher.apply(&root, Change::Created).await;
+        for _ in 0..3 {
+            assert!(matches!(rx.recv().await, Some(OrgEvent::Updated(_))));
+        }
+
+        // the directory is gone, so it can only be recognised by what was indexed below it
+        fs::remove_dir_all(&dir).unwrap();
+        watcher.apply(&dir, Change::Modified).await;
+        match rx.recv().await {
+            Some(OrgEvent::Removed(f)) => assert_eq!(name(&dir), f),
+            other => panic!("unexpected {:?}", other),
+        }
+        let files: Vec<_> = watcher
+            .index
+            .documents()
+            .iter()
+            .filter_map(|org| org.filename.clone())
+            .collect();
+        assert_eq!(vec![name(&root.join("c.org"))], files);
+
+        // nothing indexed there, nothing to retract
+        watcher
+            .apply(&root.join("gone.txt"), Change::Modified)
+            .await;
+        assert!(rx.try_recv().is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_debounce() {
+        let root = temp_dir("watch-debounce");
+        let (watcher, mut rx) = watcher_with_debounce(&root, Duration::from_millis(50));
+        let (tx, events) = mpsc::channel(16);
+        let handle = task::spawn(async move { watcher.handle_events(events).await });
+
+        let a = root.join("a.org");
+        fs::write(&a, "* Task\n").unwrap();
+        let write = notify::Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)));
+        tx.send(Ok(
+            notify::Event::new(EventKind::Create(CreateKind::File)).add_path(a.clone())
+        ))
+        .await
+        .unwrap();
+        for _ in 0..5 {
+            tx.send(Ok(write.clone().add_path(a.clone())))
+                .await
+                .unwrap();
+        }
+        for name in [".#x.org", "#x.org#", "x.org~"] {
+            let path = root.join(name);
+            fs::write(&path, "* Backup\n").unwrap();
+            tx.send(Ok(
+                notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path)
+            ))
+            .await
+            .unwrap();
+        }
+
+        // the burst is handled once, the editor's lock, auto-save and backup files never
+        match rx.recv().await {
+            Some(OrgEvent::Updated(f)) => assert_eq!(name(&a), f),
+            other => panic!("unexpected {:?}", other),
+        }
+        let next = time::timeout(Duration::from_millis(200), rx.recv()).await;
+        assert!(next.is_err(), "unexpected {:?}", next);
+
+        drop(tx);
+        handle.await.unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,21 +1,526 @@
+use crate::search::SearchHit;
+use crate::{
+    capture::{self, CaptureRequest, CaptureTemplate},
+    config::Config,
+    edit::FileLocked,
+    index::Index,
+    live::LiveSender,
+};
 use anyhow::Result;
-use axum::{routing::get, Router};
-use tracing::info;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Days, Local, NaiveDate};
+use futures::{stream, Stream};
+use org_parser::{AgendaDay, AgendaQuery, Context, Org, Query as OrgQuery, QueryItem, Section};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info};
 
-pub async fn run_server(port: u32) -> Result<()> {
-    // build our application with a route
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/", get(root));
+/// Longest range `/api/agenda` serves in one request.
+const MAX_AGENDA_DAYS: u64 = 366;
+/// Results `/api/search` returns by default and at most.
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 200;
+
+struct AppState {
+    org_path: Vec<String>,
+    index: Arc<Index>,
+    live: LiveSender,
+    ctx: Context,
+    /// Capture templates with the files they write to.
+    capture: Vec<(CaptureTemplate, PathBuf)>,
+    deadline_warning_days: u32,
+}
+
+impl AppState {
+    /// Indexed document at `path`, given as is or relative to one of the org paths.
+    fn document(&self, path: &str) -> Option<Arc<Org>> {
+        self.index
+            .get(path)
+            .or_else(|| self.index.get(&format!("/{}", path)))
+            .or_else(|| {
+                self.org_path.iter().find_map(|root| {
+                    let file = FsPath::new(root).join(path);
+                    self.index.get(&format!("{}", file.display()))
+                })
+            })
+    }
+}
+
+/// Error response with a JSON body.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+pub async fn run_server(config: &Config, index: Arc<Index>, live: LiveSender) -> Result<()> {
+    let state = Arc::new(AppState {
+        org_path: config.org_path.clone(),
+        index,
+        live,
+        ctx: config.context(),
+        capture: config
+            .capture
+            .iter()
+            .map(|t| (t.clone(), config.capture_path(t)))
+            .collect(),
+        deadline_warning_days: config.deadline_warning_days,
+    });
+
+    let app = router(state);
 
     // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.server_port)).await?;
     info!("start server");
     axum::serve(listener, app).await?;
     Ok(())
 }
 
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        // `GET /` goes to `root`
+        .route("/", get(root))
+        .route("/api/agenda", get(agenda))
+        .route("/api/files", get(files))
+        .route("/api/files/*path", get(file))
+        .route("/api/ids/:id", get(id))
+        .route("/api/events", get(events))
+        .route("/api/search", get(search))
+        .route("/api/query", get(query))
+        .route("/api/capture", post(capture))
+        .with_state(state)
+}
+
 // basic handler that responds with a static string
 async fn root() -> &'static str {
     "Hello, World!"
 }
+
+/// Comma separated values of a query parameter.
+fn split_list(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct AgendaParams {
+    /// First day, today by default.
+    from: Option<NaiveDate>,
+    /// Last day, a week from `from` by default.
+    to: Option<NaiveDate>,
+    /// Comma separated tags the items must all have.
+    tags: Option<String>,
+    /// Comma separated TODO keywords, one of which the items must have.
+    todo: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Agenda {
+    from: NaiveDate,
+    to: NaiveDate,
+    days: Vec<AgendaDay>,
+}
+
+async fn agenda(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AgendaParams>,
+) -> Result<Json<Agenda>, ApiError> {
+    let today = Local::now().date_naive();
+    let from = params.from.unwrap_or(today);
+    let to = match params.to {
+        Some(to) => to,
+        None => from
+            .checked_add_days(Days::new(6))
+            .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, "invalid from".to_string()))?,
+    };
+    if to < from {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "to is before from".to_string(),
+        ));
+    }
+    if from
+        .checked_add_days(Days::new(MAX_AGENDA_DAYS))
+        .is_some_and(|max| to >= max)
+    {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("range is longer than {} days", MAX_AGENDA_DAYS),
+        ));
+    }
+
+    let query = AgendaQuery {
+        from,
+        to,
+        today,
+        deadline_warning_days: state.deadline_warning_days,
+        tags: split_list(params.tags.as_deref()),
+        todo: split_list(params.todo.as_deref()),
+    };
+    let orgs = state.index.documents();
+    let days = org_parser::agenda(orgs.iter().map(|org| org.as_ref()), &query);
+    Ok(Json(Agenda { from, to, days }))
+}
+
+#[derive(Serialize, Default)]
+struct FileInfo {
+    file: String,
+    title: Option<String>,
+    id: Option<String>,
+    mtime: Option<DateTime<Local>>,
+    /// Number of headings at any depth.
+    headings: usize,
+    /// Headings with an open TODO keyword.
+    todo: usize,
+    /// Headings with a done keyword.
+    done: usize,
+}
+
+fn count_headings(sections: &[Section], info: &mut FileInfo) {
+    for sec in sections {
+        info.headings += 1;
+        if sec.done {
+            info.done += 1;
+        } else if sec.todo.is_some() {
+            info.todo += 1;
+        }
+        count_headings(&sec.sections, info);
+    }
+}
+
+async fn files(State(state): State<Arc<AppState>>) -> Json<Vec<FileInfo>> {
+    let mut files = vec![];
+    for org in state.index.documents() {
+        let file = org.filename.clone().unwrap_or_default();
+        let mtime = tokio::fs::metadata(&file)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok()
+            .map(DateTime::<Local>::from);
+        let mut info = FileInfo {
+            file,
+            title: org.title.clone(),
+            id: org.id.clone(),
+            mtime,
+            ..Default::default()
+        };
+        count_headings(&org.sections, &mut info);
+        files.push(info);
+    }
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+    Json(files)
+}
+
+async fn file(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+) -> Result<Response, ApiError> {
+    let org = state
+        .document(&path)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("file {} not found", path)))?;
+    Ok(Json(org.as_ref()).into_response())
+}
+
+/// What an `ID` resolves to: the whole document for file-level ids, the heading otherwise.
+#[derive(Serialize)]
+struct IdTarget<'a> {
+    file: &'a str,
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a Org>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    section: Option<&'a Section>,
+}
+
+async fn id(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let not_found = || ApiError(StatusCode::NOT_FOUND, format!("id {} not found", id));
+    let loc = state.index.locate(&id).ok_or_else(not_found)?;
+    let org = state.index.get(&loc.file).ok_or_else(not_found)?;
+    let target = match loc.line {
+        Some(line) => IdTarget {
+            file: &loc.file,
+            line: Some(line),
+            document: None,
+            section: Some(org.section_at(line).ok_or_else(not_found)?),
+        },
+        None => IdTarget {
+            file: &loc.file,
+            line: None,
+            document: Some(org.as_ref()),
+            section: None,
+        },
+    };
+    Ok(Json(target).into_response())
+}
+
+/// Reminders and document changes as server-sent events, named by their `event` field.
+async fn events(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let rx = state.live.subscribe();
+    let stream = stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let data = Event::default().event(event.name()).json_data(&event);
+                    return Some((data, rx));
+                }
+                Err(RecvError::Lagged(n)) => debug!("event stream lagged: {}", n),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<SearchHit>>, ApiError> {
+    if params.q.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "empty query".to_string()));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT);
+    Ok(Json(state.index.search(&params.q, limit)))
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    q: String,
+}
+
+async fn query(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<QueryParams>,
+) -> Result<Json<Vec<QueryItem>>, ApiError> {
+    let query: OrgQuery = params
+        .q
+        .parse()
+        .map_err(|err: anyhow::Error| ApiError(StatusCode::BAD_REQUEST, err.to_string()))?;
+    let mut orgs = state.index.documents();
+    orgs.sort_by(|a, b| a.filename.cmp(&b.filename));
+    let today = Local::now().date_naive();
+    let items = org_parser::query(orgs.iter().map(|org| org.as_ref()), &query, today);
+    Ok(Json(items))
+}
+
+async fn capture(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CaptureRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let (template, path) = state
+        .capture
+        .iter()
+        .find(|(t, _)| t.name == req.template)
+        .ok_or_else(|| {
+            ApiError(
+                StatusCode::NOT_FOUND,
+                format!("capture template {} not found", req.template),
+            )
+        })?;
+    let entry = req
+        .entry(template)
+        .map_err(|err| ApiError(StatusCode::BAD_REQUEST, err.to_string()))?;
+    let line = capture::capture(path, template.heading.as_deref(), &entry, &state.ctx)
+        .await
+        .map_err(|err| match err.downcast_ref::<FileLocked>() {
+            Some(locked) => ApiError(StatusCode::CONFLICT, locked.to_string()),
+            None => err.into(),
+        })?;
+    let file = format!("{}", path.display());
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "file": file, "line": line })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::org,
+        live::{self, LiveEvent},
+    };
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    /// Serve the API over the given documents on a free port, returning its base URL.
+    async fn serve(index: Arc<Index>, live: LiveSender) -> String {
+        let state = Arc::new(AppState {
+            org_path: vec!["/notes".to_string()],
+            index,
+            live,
+            ctx: Context::new(),
+            capture: vec![],
+            deadline_warning_days: 14,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        url
+    }
+
+    fn index() -> Arc<Index> {
+        let index = Arc::new(Index::new());
+        index.insert(org(
+            "/notes/work/todo.org",
+            ":PROPERTIES:\n:ID: todo-file\n:END:\n#+TITLE: Work\n* TODO Call Bob :phone:\nSCHEDULED: <2099-03-04 Wed 10:00>\n* Meeting\n:PROPERTIES:\n:ID: meeting\n:END:\nRoom 4 on the tomato floor.\n",
+        ));
+        index
+    }
+
+    async fn get(url: &str) -> (StatusCode, Value) {
+        let res = reqwest::get(url).await.unwrap();
+        let status = res.status();
+        (status, res.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_agenda_range() {
+        let url = serve(index(), live::channel()).await;
+
+        let (status, body) =
+            get(&format!("{}/api/agenda?from=2099-03-01&to=2099-03-07", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(7, body["days"].as_array().unwrap().len());
+        assert_eq!("Call Bob", body["days"][3]["items"][0]["title"]);
+
+        let (status, body) =
+            get(&format!("{}/api/agenda?from=2099-03-07&to=2099-03-01", url)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("to is before from", body["error"]);
+
+        let (status, _) = get(&format!("{}/api/agenda?from=2099-01-01&to=2100-01-01", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        let (status, body) =
+            get(&format!("{}/api/agenda?from=2099-01-01&to=2100-01-02", url)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("range is longer than 366 days", body["error"]);
+    }
+
+    #[tokio::test]
+    async fn test_file() {
+        let url = serve(index(), live::channel()).await;
+
+        let (status, body) = get(&format!("{}/api/files", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("/notes/work/todo.org", body[0]["file"]);
+        assert_eq!("Work", body[0]["title"]);
+        assert_eq!(2, body[0]["headings"]);
+        assert_eq!(1, body[0]["todo"]);
+
+        // relative to an org path, or absolute
+        for path in ["work/todo.org", "notes/work/todo.org"] {
+            let (status, body) = get(&format!("{}/api/files/{}", url, path)).await;
+            assert_eq!(StatusCode::OK, status, "{}", path);
+            assert_eq!("/notes/work/todo.org", body["filename"]);
+        }
+        let (status, _) = get(&format!("{}/api/files/todo.org", url)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_id() {
+        let url = serve(index(), live::channel()).await;
+
+        let (status, body) = get(&format!("{}/api/ids/todo-file", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("/notes/work/todo.org", body["file"]);
+        assert_eq!(Value::Null, body["line"]);
+        assert_eq!("Work", body["document"]["title"]);
+        assert!(body.get("section").is_none());
+
+        let (status, body) = get(&format!("{}/api/ids/meeting", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(7, body["line"]);
+        assert_eq!("Meeting", body["section"]["title"]);
+        assert!(body.get("document").is_none());
+
+        let (status, _) = get(&format!("{}/api/ids/missing", url)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_search_and_query() {
+        let url = serve(index(), live::channel()).await;
+
+        let (status, body) = get(&format!("{}/api/search?q=tomato", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, body.as_array().unwrap().len());
+        assert_eq!(serde_json::json!(["Meeting"]), body[0]["path"]);
+        let (status, _) = get(&format!("{}/api/search?q=%20", url)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+
+        let (status, body) = get(&format!("{}/api/query?q=todo:TODO%20tags:phone", url)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, body.as_array().unwrap().len());
+        assert_eq!("Call Bob", body[0]["title"]);
+        let (status, body) = get(&format!("{}/api/query?q=level:x", url)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let live = live::channel();
+        let url = serve(index(), live.clone()).await;
+        let mut res = reqwest::get(format!("{}/api/events", url)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("text/event-stream", res.headers()["content-type"]);
+
+        // the stream is subscribed by the time the response starts
+        live.send(LiveEvent::DocumentChanged {
+            file: "/notes/work/todo.org".to_string(),
+        })
+        .unwrap();
+        let chunk = res.chunk().await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+        let mut lines = chunk.lines();
+        assert_eq!(Some("event: document-changed"), lines.next());
+        let data: Value =
+            serde_json::from_str(lines.next().unwrap().strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({ "event": "document-changed", "file": "/notes/work/todo.org" }),
+            data
+        );
+    }
+}